@@ -0,0 +1,2 @@
+CREATE TABLE IF NOT EXISTS inventory_adjustments (id UUID PRIMARY KEY, product_id UUID NOT NULL REFERENCES products(id), previous_quantity INTEGER NOT NULL, new_quantity INTEGER NOT NULL, variance INTEGER NOT NULL, reason VARCHAR(50) NOT NULL, created_at TIMESTAMPTZ DEFAULT NOW());
+CREATE INDEX idx_inventory_adjustments_product ON inventory_adjustments(product_id);
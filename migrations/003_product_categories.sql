@@ -0,0 +1,2 @@
+CREATE TABLE IF NOT EXISTS product_categories (product_id UUID NOT NULL REFERENCES products(id), category_id UUID NOT NULL REFERENCES categories(id), sort_order INTEGER, PRIMARY KEY (product_id, category_id));
+CREATE INDEX idx_product_categories_category ON product_categories(category_id, sort_order);
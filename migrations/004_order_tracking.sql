@@ -0,0 +1 @@
+ALTER TABLE orders ADD COLUMN IF NOT EXISTS tracking_number VARCHAR(255), ADD COLUMN IF NOT EXISTS carrier VARCHAR(100);
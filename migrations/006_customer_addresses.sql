@@ -0,0 +1,3 @@
+CREATE TABLE IF NOT EXISTS customer_addresses (id UUID PRIMARY KEY, customer_id UUID NOT NULL, address JSONB NOT NULL, is_default BOOLEAN NOT NULL DEFAULT FALSE, created_at TIMESTAMPTZ DEFAULT NOW());
+CREATE INDEX idx_customer_addresses_customer ON customer_addresses(customer_id);
+CREATE UNIQUE INDEX idx_customer_addresses_default ON customer_addresses(customer_id) WHERE is_default;
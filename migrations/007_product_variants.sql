@@ -0,0 +1,5 @@
+CREATE TABLE IF NOT EXISTS product_variants (id UUID PRIMARY KEY, product_id UUID NOT NULL REFERENCES products(id), sku VARCHAR(100) UNIQUE NOT NULL, name VARCHAR(255) NOT NULL, price BIGINT, inventory_quantity INTEGER DEFAULT 0, created_at TIMESTAMPTZ DEFAULT NOW());
+CREATE INDEX idx_product_variants_product ON product_variants(product_id);
+ALTER TABLE cart_items ADD COLUMN variant_id UUID REFERENCES product_variants(id);
+ALTER TABLE cart_items DROP CONSTRAINT IF EXISTS cart_items_session_id_product_id_key;
+ALTER TABLE cart_items ADD CONSTRAINT cart_items_session_product_variant_key UNIQUE NULLS NOT DISTINCT (session_id, product_id, variant_id);
@@ -0,0 +1 @@
+CREATE TABLE IF NOT EXISTS idempotency_keys (customer_email TEXT NOT NULL, key TEXT NOT NULL, order_id UUID NOT NULL REFERENCES orders(id), created_at TIMESTAMPTZ NOT NULL DEFAULT NOW(), PRIMARY KEY (customer_email, key));
@@ -0,0 +1 @@
+ALTER TABLE products ADD COLUMN IF NOT EXISTS max_per_order INT CHECK (max_per_order > 0);
@@ -0,0 +1,2 @@
+ALTER TABLE products ADD COLUMN IF NOT EXISTS inventory_policy TEXT NOT NULL DEFAULT 'deny' CHECK (inventory_policy IN ('deny', 'continue'));
+ALTER TABLE products ADD COLUMN IF NOT EXISTS preorder_until TIMESTAMPTZ;
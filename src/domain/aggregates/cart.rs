@@ -1,9 +1,12 @@
 //! Cart Aggregate
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Utc};
 use rust_decimal::Decimal;
 use uuid::Uuid;
-use crate::domain::value_objects::Money;
+use crate::domain::value_objects::{Discount, DiscountKind, Money, WeightUnit};
+use crate::domain::aggregates::order::Address;
+use crate::domain::aggregates::product::Product;
+use crate::domain::services::TaxStrategy;
 
 #[derive(Clone, Debug)]
 pub struct Cart {
@@ -12,7 +15,13 @@ pub struct Cart {
     session_id: Option<String>,
     items: Vec<CartItem>,
     subtotal: Money,
+    discount: Option<Discount>,
+    discount_total: Money,
+    total: Money,
     currency: String,
+    currency_locked: bool,
+    ttl: Option<Duration>,
+    expires_at: Option<DateTime<Utc>>,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
 }
@@ -25,9 +34,30 @@ pub struct CartItem {
     pub sku: String,
     pub quantity: u32,
     pub unit_price: Money,
+    pub weight_grams: Option<u32>,
 }
 
+/// How overlapping items are reconciled when two carts are merged
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum MergeStrategy { #[default] Sum, KeepHigher, KeepCustomer }
+
+/// What `add_item_limited` does when an add would take a product past its per-order maximum
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum QuantityLimit { #[default] Reject, Cap }
+
 impl CartItem {
+    /// A line for `product`, taking the price, SKU and unit weight (`Product::weight_for`) from the selected variant where it sets them.
+    pub fn from_product(product: &Product, variant_id: Option<&str>, quantity: u32) -> Self {
+        let variant = variant_id.and_then(|id| product.variants().iter().find(|v| v.id == id));
+        Self {
+            product_id: product.id().to_string(), variant_id: variant.map(|v| v.id.clone()),
+            name: variant.map_or_else(|| product.name().to_string(), |v| format!("{} - {}", product.name(), v.name)),
+            sku: variant.and_then(|v| v.sku.as_ref()).unwrap_or(product.sku()).to_string(), quantity,
+            unit_price: variant.map_or_else(|| product.price().clone(), |v| v.price.clone()),
+            weight_grams: product.weight_for(variant_id, WeightUnit::Grams).map(|g| g.round() as u32),
+        }
+    }
+    
     pub fn line_total(&self) -> Money { self.unit_price.multiply(self.quantity) }
 }
 
@@ -35,8 +65,9 @@ impl Cart {
     pub fn new(currency: &str) -> Self {
         Self {
             id: Uuid::new_v4().to_string(), customer_id: None, session_id: None,
-            items: vec![], subtotal: Money::zero(currency), currency: currency.to_string(),
-            created_at: Utc::now(), updated_at: Utc::now(),
+            items: vec![], subtotal: Money::zero(currency), discount: None,
+            discount_total: Money::zero(currency), total: Money::zero(currency), currency: currency.to_string(), currency_locked: false,
+            ttl: None, expires_at: None, created_at: Utc::now(), updated_at: Utc::now(),
         }
     }
     
@@ -46,11 +77,40 @@ impl Cart {
         cart
     }
     
+    /// Gives the cart a sliding expiry: it lapses `ttl` after its last item change.
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self.touch_expiry(ttl);
+        self
+    }
+    
     pub fn id(&self) -> &str { &self.id }
     pub fn items(&self) -> &[CartItem] { &self.items }
     pub fn subtotal(&self) -> &Money { &self.subtotal }
+    pub fn discount(&self) -> Option<&Discount> { self.discount.as_ref() }
+    pub fn discount_total(&self) -> Money { self.discount_total.clone() }
+    /// Subtotal less discounts, never below zero.
+    pub fn total(&self) -> &Money { &self.total }
     pub fn item_count(&self) -> usize { self.items.len() }
     pub fn is_empty(&self) -> bool { self.items.is_empty() }
+    pub fn currency(&self) -> &str { &self.currency }
+    pub fn is_currency_locked(&self) -> bool { self.currency_locked }
+    pub fn expires_at(&self) -> Option<DateTime<Utc>> { self.expires_at }
+    pub fn is_expired(&self, now: DateTime<Utc>) -> bool { self.expires_at.is_some_and(|e| now >= e) }
+    pub fn touch_expiry(&mut self, ttl: Duration) { self.expires_at = Some(Utc::now() + ttl); }
+    
+    /// Shipping weight of the cart; items without a weight contribute nothing.
+    pub fn total_weight_grams(&self) -> u32 {
+        self.items.iter().filter_map(|i| i.weight_grams.map(|w| w.saturating_mul(i.quantity))).fold(0, u32::saturating_add)
+    }
+    
+    /// Switches the cart currency; rejected once the first item has locked it (until `clear`).
+    pub fn set_currency(&mut self, currency: &str) -> Result<(), CartError> {
+        if self.currency_locked && self.currency != currency { return Err(CartError::CurrencyLocked); }
+        self.currency = currency.to_string();
+        self.recalculate();
+        Ok(())
+    }
     
     pub fn add_item(&mut self, item: CartItem) {
         if let Some(existing) = self.items.iter_mut().find(|i| i.product_id == item.product_id && i.variant_id == item.variant_id) {
@@ -58,13 +118,32 @@ impl Cart {
         } else {
             self.items.push(item);
         }
+        self.currency_locked = true;
+        self.refresh_expiry();
         self.recalculate();
     }
     
+    /// `add_item` honouring a product's per-order maximum, counted across all of its variants. With
+    /// `QuantityLimit::Cap` the quantity is trimmed to what still fits; returns the quantity actually added.
+    pub fn add_item_limited(&mut self, mut item: CartItem, max_per_order: Option<u32>, limit: QuantityLimit) -> Result<u32, CartError> {
+        if let Some(max) = max_per_order {
+            let in_cart: u32 = self.items.iter().filter(|i| i.product_id == item.product_id).map(|i| i.quantity).sum();
+            let room = max.saturating_sub(in_cart);
+            if item.quantity > room {
+                if limit == QuantityLimit::Reject || room == 0 { return Err(CartError::QuantityLimitExceeded { max }); }
+                item.quantity = room;
+            }
+        }
+        let added = item.quantity;
+        self.add_item(item);
+        Ok(added)
+    }
+    
     pub fn update_quantity(&mut self, product_id: &str, quantity: u32) -> Result<(), CartError> {
         let item = self.items.iter_mut().find(|i| i.product_id == product_id).ok_or(CartError::ItemNotFound)?;
         if quantity == 0 { self.items.retain(|i| i.product_id != product_id); }
         else { item.quantity = quantity; }
+        self.refresh_expiry();
         self.recalculate();
         Ok(())
     }
@@ -73,34 +152,270 @@ impl Cart {
         let before = self.items.len();
         self.items.retain(|i| i.product_id != product_id);
         if self.items.len() == before { return Err(CartError::ItemNotFound); }
+        self.refresh_expiry();
+        self.recalculate();
+        Ok(())
+    }
+    
+    /// Applies a discount code, replacing any code already on the cart.
+    pub fn apply_discount(&mut self, discount: Discount) -> Result<(), CartError> {
+        match &discount.kind {
+            DiscountKind::Percentage(pct) if pct.is_sign_negative() || *pct > Decimal::ONE_HUNDRED => return Err(CartError::InvalidDiscount),
+            DiscountKind::FixedAmount(amount) if amount.currency() != self.currency => return Err(CartError::CurrencyMismatch),
+            _ => {}
+        }
+        self.discount = Some(discount);
         self.recalculate();
         Ok(())
     }
     
-    pub fn clear(&mut self) { self.items.clear(); self.recalculate(); }
+    pub fn remove_discount(&mut self) { self.discount = None; self.recalculate(); }
+    
+    /// Pre-checkout tax estimate; zero until the shopper has given an address.
+    pub fn estimate_tax(&self, strategy: &dyn TaxStrategy, address: Option<&Address>) -> Money {
+        match address {
+            Some(address) => strategy.tax_for(&self.total, address),
+            None => Money::zero(&self.currency),
+        }
+    }
+    
+    /// Folds `other` (the guest cart) into this (customer) cart, resolving overlaps with `strategy`.
+    pub fn merge(&mut self, other: Cart, strategy: MergeStrategy) -> Result<(), CartError> { self.merge_capped(other, strategy, |_| None) }
+    
+    /// Like `merge`, but each merged line is clamped to `max_for(item)` (e.g. stock on hand); lines capped to zero are dropped.
+    pub fn merge_capped(&mut self, other: Cart, strategy: MergeStrategy, max_for: impl Fn(&CartItem) -> Option<u32>) -> Result<(), CartError> {
+        if other.currency != self.currency && !other.items.is_empty() { return Err(CartError::CurrencyMismatch); }
+        for item in other.items {
+            let index = match self.items.iter().position(|i| i.product_id == item.product_id && i.variant_id == item.variant_id) {
+                Some(index) => {
+                    let existing = &mut self.items[index];
+                    match strategy {
+                        MergeStrategy::Sum => existing.quantity = existing.quantity.saturating_add(item.quantity),
+                        MergeStrategy::KeepHigher => existing.quantity = existing.quantity.max(item.quantity),
+                        MergeStrategy::KeepCustomer => {}
+                    }
+                    index
+                }
+                None => { self.items.push(item); self.items.len() - 1 }
+            };
+            if let Some(max) = max_for(&self.items[index]) {
+                if max == 0 { self.items.remove(index); } else { self.items[index].quantity = self.items[index].quantity.min(max); }
+            }
+        }
+        self.currency_locked |= !self.items.is_empty();
+        self.refresh_expiry();
+        self.recalculate();
+        Ok(())
+    }
+    
+    pub fn clear(&mut self) { self.items.clear(); self.currency_locked = false; self.recalculate(); }
+    
+    fn refresh_expiry(&mut self) { if let Some(ttl) = self.ttl { self.touch_expiry(ttl); } }
+    
+    /// Sums raw amounts when every line is in the cart currency, building a single `Money`; mixed
+    /// currencies fall back to the `Money::add` fold, which skips mismatched lines.
+    fn sum_line_totals(&self) -> Money {
+        if self.items.iter().all(|i| i.unit_price.currency() == self.currency) {
+            return Money::new(self.items.iter().map(|i| i.unit_price.amount() * Decimal::from(i.quantity)).sum(), &self.currency);
+        }
+        self.items.iter().fold(Money::zero(&self.currency), |acc, i| acc.add(&i.line_total()).unwrap_or(acc))
+    }
     
     fn recalculate(&mut self) {
-        self.subtotal = self.items.iter().fold(Money::zero(&self.currency), |acc, i| acc.add(&i.line_total()).unwrap_or(acc));
+        self.subtotal = self.sum_line_totals();
+        self.discount_total = self.discount.as_ref().and_then(|d| d.amount_off(&self.subtotal).ok()).unwrap_or_else(|| Money::zero(&self.currency));
+        self.total = self.subtotal.subtract(&self.discount_total).unwrap_or_else(|_| self.subtotal.clone());
         self.updated_at = Utc::now();
     }
 }
 
-#[derive(Debug, Clone)] pub enum CartError { ItemNotFound }
+#[derive(Debug, Clone)] pub enum CartError { ItemNotFound, CurrencyLocked, CurrencyMismatch, InvalidDiscount, QuantityLimitExceeded { max: u32 } }
 impl std::error::Error for CartError {}
 impl std::fmt::Display for CartError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result { write!(f, "Item not found") }
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::ItemNotFound => write!(f, "Item not found"),
+            Self::CurrencyLocked => write!(f, "Cart currency is locked"),
+            Self::CurrencyMismatch => write!(f, "Currency mismatch"),
+            Self::InvalidDiscount => write!(f, "Invalid discount"),
+            Self::QuantityLimitExceeded { max } => write!(f, "Limit of {} per order for this product", max),
+        }
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::aggregates::product::Variant;
+    use crate::domain::value_objects::{Quantity, Sku};
     #[test]
     fn test_cart_operations() {
         let mut cart = Cart::new("USD");
-        cart.add_item(CartItem { product_id: "P1".into(), variant_id: None, name: "Widget".into(), sku: "W1".into(), quantity: 2, unit_price: Money::usd(Decimal::new(10, 0)) });
+        cart.add_item(CartItem { product_id: "P1".into(), variant_id: None, name: "Widget".into(), sku: "W1".into(), quantity: 2, unit_price: Money::usd(Decimal::new(10, 0)), weight_grams: None });
         assert_eq!(cart.item_count(), 1);
         assert_eq!(cart.subtotal().amount(), Decimal::new(20, 0));
-        cart.add_item(CartItem { product_id: "P1".into(), variant_id: None, name: "Widget".into(), sku: "W1".into(), quantity: 1, unit_price: Money::usd(Decimal::new(10, 0)) });
+        cart.add_item(CartItem { product_id: "P1".into(), variant_id: None, name: "Widget".into(), sku: "W1".into(), quantity: 1, unit_price: Money::usd(Decimal::new(10, 0)), weight_grams: None });
         assert_eq!(cart.items()[0].quantity, 3); // Merged
     }
+    
+    #[test]
+    fn test_add_item_limited() {
+        let item = |quantity| CartItem { product_id: "P1".into(), variant_id: None, name: "Console".into(), sku: "C1".into(), quantity, unit_price: Money::usd(Decimal::new(10, 0)), weight_grams: None };
+        let mut cart = Cart::new("USD");
+        assert_eq!(cart.add_item_limited(item(1), Some(2), QuantityLimit::Reject).unwrap(), 1);
+        assert!(matches!(cart.add_item_limited(item(2), Some(2), QuantityLimit::Reject), Err(CartError::QuantityLimitExceeded { max: 2 })));
+        assert_eq!(cart.items()[0].quantity, 1);
+        assert_eq!(cart.add_item_limited(item(5), Some(2), QuantityLimit::Cap).unwrap(), 1);
+        assert_eq!(cart.items()[0].quantity, 2);
+        assert!(cart.add_item_limited(item(1), Some(2), QuantityLimit::Cap).is_err());
+        assert_eq!(cart.add_item_limited(item(7), None, QuantityLimit::Reject).unwrap(), 7);
+    }
+    #[test]
+    fn test_cart_expiry() {
+        let cart = Cart::new("USD").with_ttl(Duration::hours(1));
+        assert!(!cart.is_expired(Utc::now()));
+        assert!(cart.is_expired(Utc::now() + Duration::hours(2)));
+        assert!(!Cart::new("USD").is_expired(Utc::now() + Duration::days(365)));
+    }
+    
+    #[test]
+    fn test_add_item_extends_expiry() {
+        let mut cart = Cart::for_customer("CUST001", "USD").with_ttl(Duration::hours(1));
+        cart.touch_expiry(Duration::seconds(-1));
+        assert!(cart.is_expired(Utc::now()));
+        cart.add_item(widget(1));
+        assert!(!cart.is_expired(Utc::now()));
+        assert!(cart.expires_at().unwrap() > Utc::now() + Duration::minutes(59));
+    }
+    
+    #[test]
+    fn test_total_weight_grams() {
+        let mut cart = Cart::new("USD");
+        cart.add_item(CartItem { weight_grams: Some(250), ..widget(2) });
+        cart.add_item(CartItem { product_id: "P2".into(), weight_grams: Some(1000), ..widget(1) });
+        cart.add_item(CartItem { product_id: "EBOOK".into(), ..widget(4) });
+        assert_eq!(cart.total_weight_grams(), 1500);
+    }
+    
+    #[test]
+    fn test_discounts() {
+        let mut cart = Cart::new("USD");
+        cart.add_item(widget(3));
+        cart.apply_discount(Discount { code: "TENOFF".into(), kind: DiscountKind::Percentage(Decimal::new(10, 0)) }).unwrap();
+        assert_eq!(cart.discount_total().amount(), Decimal::new(3, 0));
+        assert_eq!(cart.total().amount(), Decimal::new(27, 0));
+        cart.apply_discount(Discount { code: "BIG".into(), kind: DiscountKind::FixedAmount(Money::usd(Decimal::new(50, 0))) }).unwrap();
+        assert_eq!(cart.total().amount(), Decimal::ZERO);
+        let eur = Discount { code: "EUR5".into(), kind: DiscountKind::FixedAmount(Money::new(Decimal::new(5, 0), "EUR")) };
+        assert!(matches!(cart.apply_discount(eur), Err(CartError::CurrencyMismatch)));
+        assert_eq!(cart.discount().unwrap().code, "BIG");
+    }
+    
+    #[test]
+    fn test_estimate_tax() {
+        use crate::domain::services::RegionalTax;
+        let mut cart = Cart::new("USD");
+        cart.add_item(widget(3));
+        let tax = RegionalTax::new().with_rate("NG", Decimal::new(75, 3));
+        let lagos = Address { city: "Lagos".into(), country: "NG".into(), ..Default::default() };
+        assert_eq!(cart.estimate_tax(&tax, Some(&lagos)).amount(), Decimal::new(225, 2));
+        assert!(cart.estimate_tax(&tax, None).amount().is_zero());
+    }
+    
+    #[test]
+    fn test_currency_locking() {
+        let mut cart = Cart::new("USD");
+        assert!(!cart.is_currency_locked());
+        cart.set_currency("EUR").unwrap();
+        cart.set_currency("USD").unwrap();
+        cart.add_item(widget(1));
+        assert!(cart.is_currency_locked());
+        assert!(matches!(cart.set_currency("EUR"), Err(CartError::CurrencyLocked)));
+        assert_eq!(cart.currency(), "USD");
+        cart.clear();
+        assert!(!cart.is_currency_locked());
+        cart.set_currency("EUR").unwrap();
+        assert_eq!(cart.subtotal().currency(), "EUR");
+    }
+    
+    fn widget(quantity: u32) -> CartItem {
+        CartItem { product_id: "P1".into(), variant_id: None, name: "Widget".into(), sku: "W1".into(), quantity, unit_price: Money::usd(Decimal::new(10, 0)), weight_grams: None }
+    }
+    
+    fn merged(strategy: MergeStrategy) -> Cart {
+        let mut customer = Cart::for_customer("CUST001", "USD");
+        customer.add_item(widget(2));
+        let mut guest = Cart::new("USD");
+        guest.add_item(widget(3));
+        customer.merge(guest, strategy).unwrap();
+        customer
+    }
+    
+    #[test]
+    fn test_merge_strategies() {
+        assert_eq!(MergeStrategy::default(), MergeStrategy::Sum);
+        let sum = merged(MergeStrategy::Sum);
+        assert_eq!(sum.items()[0].quantity, 5);
+        assert_eq!(sum.subtotal().amount(), Decimal::new(50, 0));
+        assert_eq!(merged(MergeStrategy::KeepHigher).items()[0].quantity, 3);
+        assert_eq!(merged(MergeStrategy::KeepCustomer).items()[0].quantity, 2);
+    }
+    
+    #[test]
+    fn test_variant_weights_in_cart_total() {
+        let mut product = Product::create(Sku::new("TEE").unwrap(), "Tee", Money::usd(Decimal::new(20, 0)));
+        product.set_weight(200.0, WeightUnit::Grams);
+        let variant = |id: &str, weight| Variant { id: id.into(), sku: None, name: id.into(), price: Money::usd(Decimal::new(20, 0)), inventory: Quantity::new(10), weight, weight_unit: WeightUnit::Kilograms, image_ids: vec![], low_stock_threshold: None };
+        product.add_variant(variant("S", Some(0.15)));
+        product.add_variant(variant("XL", Some(0.3)));
+        product.add_variant(variant("M", None));
+        let mut cart = Cart::new("USD");
+        cart.add_item(CartItem::from_product(&product, Some("S"), 2));
+        cart.add_item(CartItem::from_product(&product, Some("XL"), 1));
+        cart.add_item(CartItem::from_product(&product, Some("M"), 1));
+        assert_eq!(cart.items()[0].weight_grams, Some(150));
+        assert_eq!(cart.items()[1].weight_grams, Some(300));
+        assert_eq!(cart.total_weight_grams(), 800);
+    }
+    
+    #[test]
+    fn test_merge_capped_to_stock() {
+        let mut customer = Cart::for_customer("CUST001", "USD");
+        customer.add_item(widget(2));
+        let mut guest = Cart::new("USD");
+        guest.add_item(widget(3));
+        guest.add_item(CartItem { product_id: "P2".into(), ..widget(1) });
+        customer.merge_capped(guest, MergeStrategy::Sum, |i| Some(if i.product_id == "P1" { 4 } else { 0 })).unwrap();
+        assert_eq!(customer.item_count(), 1);
+        assert_eq!(customer.items()[0].quantity, 4);
+        assert_eq!(customer.subtotal().amount(), Decimal::new(40, 0));
+    }
+    
+    #[test]
+    fn test_merge_guest_cart() {
+        let mut customer = Cart::for_customer("CUST001", "USD");
+        customer.add_item(widget(1));
+        let mut guest = Cart::new("USD");
+        guest.add_item(widget(2));
+        guest.add_item(CartItem { product_id: "P2".into(), ..widget(1) });
+        customer.merge(guest, MergeStrategy::default()).unwrap();
+        assert_eq!(customer.item_count(), 2);
+        assert_eq!(customer.items()[0].quantity, 3);
+        assert_eq!(customer.subtotal().amount(), Decimal::new(40, 0));
+        let mut naira = Cart::new("NGN");
+        naira.add_item(CartItem { unit_price: Money::new(Decimal::new(500, 0), "NGN"), ..widget(1) });
+        assert!(matches!(customer.merge(naira, MergeStrategy::Sum), Err(CartError::CurrencyMismatch)));
+        assert_eq!(customer.item_count(), 2);
+    }
+    
+    #[test]
+    fn test_large_cart_subtotal_matches_fold() {
+        let mut cart = Cart::new("USD");
+        for n in 0..1000u32 {
+            cart.add_item(CartItem { product_id: format!("P{}", n), unit_price: Money::usd(Decimal::new(1999 + n as i64, 2)), ..widget(n % 7 + 1) });
+        }
+        let naive = cart.items().iter().fold(Money::zero("USD"), |acc, i| acc.add(&i.line_total()).unwrap());
+        assert_eq!(cart.subtotal().amount(), naive.amount());
+        assert_eq!(cart.subtotal().currency(), "USD");
+    }
 }
@@ -3,6 +3,6 @@ pub mod product;
 pub mod order;
 pub mod cart;
 
-pub use product::{Product, ProductError, ProductStatus};
-pub use order::{Order, OrderError, OrderStatus, LineItem, Address};
-pub use cart::{Cart, CartError, CartItem};
+pub use product::{Product, ProductError, ProductStatus, Variant, ProductImage, InventoryPolicy, StockStatus, PublishRequirements};
+pub use order::{Order, OrderError, OrderStatus, LineItem, Address, AllocationStatus, PaymentStatus, FulfillmentStatus};
+pub use cart::{Cart, CartError, CartItem, MergeStrategy, QuantityLimit};
@@ -1,10 +1,13 @@
 //! Order Aggregate
 
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, NaiveDate, Utc};
 use rust_decimal::Decimal;
+use std::collections::HashMap;
 use uuid::Uuid;
-use crate::domain::value_objects::Money;
+use crate::domain::value_objects::{Money, WeightUnit};
 use crate::domain::events::{DomainEvent, OrderEvent};
+use crate::domain::services::{add_business_days, TaxStrategy};
+use crate::domain::aggregates::cart::CartItem;
 
 #[derive(Clone, Debug)]
 pub struct Order {
@@ -21,55 +24,415 @@ pub struct Order {
     tax: Money,
     discount: Money,
     total: Money,
+    authorized: Money,
+    captured: Money,
+    refunded: Money,
     shipping_address: Option<Address>,
     billing_address: Option<Address>,
     notes: Option<String>,
+    is_gift: bool,
+    gift_message: Option<String>,
+    requires_allocation: bool,
+    allocations: HashMap<String, u32>,
+    fulfilled: HashMap<String, u32>,
+    tracking_number: Option<String>,
+    fulfillment_due_at: Option<DateTime<Utc>>,
     created_at: DateTime<Utc>,
     updated_at: DateTime<Utc>,
     events: Vec<DomainEvent>,
 }
 
-#[derive(Clone, Debug)] pub struct LineItem { pub id: String, pub product_id: String, pub name: String, pub sku: String, pub quantity: u32, pub unit_price: Money, pub total: Money }
+/// `weight` is captured per unit at order time; `None` marks a digital line with no shipping weight.
+/// Non-`taxable` lines (e.g. gift cards) are excluded from the taxable base. `fulfilled` is set once the full quantity has shipped.
+#[derive(Clone, Debug)] pub struct LineItem { pub id: String, pub product_id: String, pub name: String, pub sku: String, pub quantity: u32, pub unit_price: Money, pub total: Money, pub weight: Option<f64>, pub weight_unit: WeightUnit, pub taxable: bool, pub fulfilled: bool }
+
+impl Default for LineItem {
+    fn default() -> Self {
+        Self { id: String::new(), product_id: String::new(), name: String::new(), sku: String::new(), quantity: 0, unit_price: Money::default(), total: Money::default(), weight: None, weight_unit: WeightUnit::default(), taxable: true, fulfilled: false }
+    }
+}
+
+impl LineItem {
+    /// Captures a cart line at checkout, carrying its unit weight so `Order::total_weight` matches the cart's.
+    pub fn from_cart_item(id: impl Into<String>, item: &CartItem) -> Self {
+        Self {
+            id: id.into(), product_id: item.product_id.clone(), name: item.name.clone(), sku: item.sku.clone(), quantity: item.quantity,
+            unit_price: item.unit_price.clone(), total: item.line_total(), weight: item.weight_grams.map(f64::from), weight_unit: WeightUnit::Grams, ..Default::default()
+        }
+    }
+    
+    /// A gift card sale: weightless and non-`taxable` by default, since tax is due when the card is redeemed.
+    pub fn gift_card(id: impl Into<String>, value: Money) -> Self {
+        Self { id: id.into(), name: "Gift card".into(), quantity: 1, unit_price: value.clone(), total: value, taxable: false, ..Default::default() }
+    }
+}
 #[derive(Clone, Debug, Default)] pub struct Address { pub name: String, pub street1: String, pub street2: Option<String>, pub city: String, pub state: Option<String>, pub zip: String, pub country: String }
 #[derive(Clone, Debug, Default, PartialEq, Eq)] pub enum OrderStatus { #[default] Pending, Confirmed, Processing, Shipped, Delivered, Cancelled, Refunded }
+
+impl OrderStatus {
+    /// Allowed status transitions; anything not listed here is rejected with `OrderError::InvalidTransition`.
+    pub fn can_transition_to(&self, to: &OrderStatus) -> bool {
+        use OrderStatus::*;
+        matches!((self, to),
+            (Pending, Confirmed | Processing | Cancelled)
+            | (Confirmed, Processing | Shipped | Cancelled)
+            | (Processing, Shipped | Cancelled)
+            | (Shipped, Delivered)
+            | (Delivered, Refunded))
+    }
+}
 #[derive(Clone, Debug, Default, PartialEq, Eq)] pub enum FulfillmentStatus { #[default] Unfulfilled, Partial, Fulfilled }
-#[derive(Clone, Debug, Default, PartialEq, Eq)] pub enum PaymentStatus { #[default] Pending, Authorized, Paid, Refunded, Voided }
+#[derive(Clone, Debug, Default, PartialEq, Eq)] pub enum PaymentStatus { #[default] Pending, Authorized, PartiallyPaid, Paid, PartiallyRefunded, Refunded, Voided }
+#[derive(Clone, Debug, Default, PartialEq, Eq)] pub enum AllocationStatus { #[default] NotRequired, Awaiting, Allocated }
+
+pub const MAX_GIFT_MESSAGE_LEN: usize = 500;
 
 impl Order {
     pub fn create(order_number: u64, customer_id: impl Into<String>, email: impl Into<String>, currency: &str) -> Self {
         let id = Uuid::new_v4().to_string();
         let now = Utc::now();
-        Self {
-            id: id.clone(), order_number, customer_id: customer_id.into(), email: email.into(),
+        let customer_id = customer_id.into();
+        let mut order = Self {
+            id: id.clone(), order_number, customer_id: customer_id.clone(), email: email.into(),
             status: OrderStatus::Pending, fulfillment: FulfillmentStatus::Unfulfilled, payment: PaymentStatus::Pending,
             items: vec![], subtotal: Money::zero(currency), shipping: Money::zero(currency), tax: Money::zero(currency),
-            discount: Money::zero(currency), total: Money::zero(currency), shipping_address: None, billing_address: None,
-            notes: None, created_at: now, updated_at: now, events: vec![],
+            discount: Money::zero(currency), total: Money::zero(currency), authorized: Money::zero(currency), captured: Money::zero(currency), refunded: Money::zero(currency), shipping_address: None, billing_address: None,
+            notes: None, is_gift: false, gift_message: None, requires_allocation: false, allocations: HashMap::new(), fulfilled: HashMap::new(), tracking_number: None, fulfillment_due_at: None, created_at: now, updated_at: now, events: vec![],
+        };
+        order.raise_event(DomainEvent::Order(OrderEvent::Created { order_id: id, customer_id }));
+        order
+    }
+    
+    /// Rebuilds an order's lifecycle state (status, payment, fulfillment, tracking) from its event history, e.g. for
+    /// audits. Line items are not part of the history: the total comes from `Confirmed`, and `Refunded` amounts count
+    /// against it as in `refund` (without a confirmed total a refund is only ever partial). The history must start with
+    /// `Created`, and a step the order could not have taken fails with `InvalidTransition`.
+    pub fn replay(events: &[OrderEvent]) -> Result<Order, OrderError> {
+        let Some((OrderEvent::Created { order_id, customer_id }, rest)) = events.split_first() else { return Err(OrderError::InvalidHistory) };
+        let mut order = Self::create(0, customer_id.clone(), "", "XXX");
+        order.id = order_id.clone();
+        order.events.clear();
+        for event in rest { order.apply(event)?; }
+        Ok(order)
+    }
+    
+    fn apply(&mut self, event: &OrderEvent) -> Result<(), OrderError> {
+        let transition = |order: &mut Self, to: OrderStatus| {
+            if !order.status.can_transition_to(&to) { return Err(order.invalid_transition(to)); }
+            order.status = to;
+            Ok(())
+        };
+        match event {
+            OrderEvent::Created { .. } => return Err(OrderError::InvalidHistory),
+            OrderEvent::Confirmed { total, .. } => {
+                transition(self, OrderStatus::Confirmed)?;
+                self.total = Money::new(*total, self.total.currency());
+            }
+            OrderEvent::Paid { .. } => {
+                if matches!(self.status, OrderStatus::Cancelled | OrderStatus::Refunded) { return Err(self.invalid_transition(OrderStatus::Processing)); }
+                if self.status.can_transition_to(&OrderStatus::Processing) { self.status = OrderStatus::Processing; }
+                self.payment = PaymentStatus::Paid;
+            }
+            OrderEvent::PartiallyFulfilled { .. } => {
+                if !matches!(self.status, OrderStatus::Confirmed | OrderStatus::Processing) { return Err(self.invalid_transition(OrderStatus::Shipped)); }
+                self.fulfillment = FulfillmentStatus::Partial;
+            }
+            OrderEvent::Shipped { tracking, .. } => {
+                transition(self, OrderStatus::Shipped)?;
+                self.fulfillment = FulfillmentStatus::Fulfilled;
+                self.tracking_number = tracking.clone();
+            }
+            OrderEvent::Delivered { .. } => transition(self, OrderStatus::Delivered)?,
+            OrderEvent::Cancelled { .. } => transition(self, OrderStatus::Cancelled)?,
+            OrderEvent::Refunded { amount, .. } => {
+                if !matches!(self.payment, PaymentStatus::Paid | PaymentStatus::PartiallyPaid | PaymentStatus::PartiallyRefunded) { return Err(OrderError::NotPaid); }
+                self.refunded = Money::new(self.refunded.amount() + *amount, self.refunded.currency());
+                let fully = self.total.amount() > Decimal::ZERO && self.refunded.amount() >= self.total.amount();
+                self.payment = if fully { PaymentStatus::Refunded } else { PaymentStatus::PartiallyRefunded };
+            }
         }
+        Ok(())
     }
     
     pub fn id(&self) -> &str { &self.id }
     pub fn order_number(&self) -> u64 { self.order_number }
     pub fn status(&self) -> &OrderStatus { &self.status }
+    pub fn subtotal(&self) -> &Money { &self.subtotal }
     pub fn total(&self) -> &Money { &self.total }
+    pub fn payment_status(&self) -> &PaymentStatus { &self.payment }
+    pub fn fulfillment_status(&self) -> &FulfillmentStatus { &self.fulfillment }
+    pub fn tracking_number(&self) -> Option<&str> { self.tracking_number.as_deref() }
+    pub fn fulfillment_due_at(&self) -> Option<DateTime<Utc>> { self.fulfillment_due_at }
+    
+    /// Sets the fulfillment SLA deadline `business_days` after `from`, skipping weekends and `holidays`.
+    pub fn set_fulfillment_sla(&mut self, from: DateTime<Utc>, business_days: u32, holidays: &[NaiveDate]) {
+        self.fulfillment_due_at = Some(add_business_days(from, business_days, holidays));
+        self.touch();
+    }
+    pub fn captured_total(&self) -> &Money { &self.captured }
+    pub fn refunded_total(&self) -> &Money { &self.refunded }
     pub fn items(&self) -> &[LineItem] { &self.items }
     
     pub fn add_item(&mut self, item: LineItem) { self.items.push(item); self.recalculate(); }
     
+    pub fn update_item_quantity(&mut self, line_item_id: &str, quantity: u32) -> Result<(), OrderError> {
+        if self.status != OrderStatus::Pending { return Err(OrderError::Locked); }
+        let item = self.items.iter_mut().find(|i| i.id == line_item_id).ok_or(OrderError::ItemNotFound)?;
+        if quantity == 0 { self.items.retain(|i| i.id != line_item_id); }
+        else { item.quantity = quantity; item.total = item.unit_price.multiply(quantity); }
+        self.recalculate();
+        Ok(())
+    }
+    
+    pub fn remove_item(&mut self, line_item_id: &str) -> Result<(), OrderError> {
+        if self.status != OrderStatus::Pending { return Err(OrderError::Locked); }
+        let before = self.items.len();
+        self.items.retain(|i| i.id != line_item_id);
+        if self.items.len() == before { return Err(OrderError::ItemNotFound); }
+        self.recalculate();
+        Ok(())
+    }
+    
+    pub fn shipping_address(&self) -> Option<&Address> { self.shipping_address.as_ref() }
+    
+    /// Corrects the destination and re-taxes the order for it; only allowed while nothing has been fulfilled or shipped.
+    pub fn set_shipping_address(&mut self, address: Address, tax: &dyn TaxStrategy) -> Result<(), OrderError> {
+        if self.fulfillment != FulfillmentStatus::Unfulfilled || matches!(self.status, OrderStatus::Shipped | OrderStatus::Delivered) { return Err(OrderError::AlreadyFulfilled); }
+        self.shipping_address = Some(address);
+        self.apply_tax(tax);
+        Ok(())
+    }
+    
+    pub fn is_gift(&self) -> bool { self.is_gift }
+    pub fn gift_message(&self) -> Option<&str> { self.gift_message.as_deref() }
+    
+    pub fn set_gift_message(&mut self, message: impl Into<String>) -> Result<(), OrderError> {
+        let message = message.into();
+        if message.chars().count() > MAX_GIFT_MESSAGE_LEN { return Err(OrderError::GiftMessageTooLong); }
+        self.is_gift = true;
+        self.gift_message = Some(message);
+        self.touch();
+        Ok(())
+    }
+    
+    /// Packing slip for the warehouse; carries the gift message but no prices.
+    pub fn packing_slip(&self) -> String {
+        let mut slip = format!("Order #{}\n", self.order_number);
+        for i in &self.items { slip.push_str(&format!("{} x {} ({})\n", i.quantity, i.name, i.sku)); }
+        if let Some(message) = &self.gift_message { slip.push_str(&format!("Gift message: {}\n", message)); }
+        slip
+    }
+    
+    /// Invoice for the buyer; the gift message is deliberately omitted.
+    pub fn invoice(&self) -> String {
+        let mut invoice = format!("Invoice for order #{}\n", self.order_number);
+        for i in &self.items { invoice.push_str(&format!("{} x {} @ {} = {}\n", i.quantity, i.name, i.unit_price.amount(), i.total.amount())); }
+        invoice.push_str(&format!("Total: {} {}\n", self.total.amount(), self.total.currency()));
+        invoice
+    }
+    
+    pub fn taxable_subtotal(&self) -> Money {
+        self.items.iter().filter(|i| i.taxable).fold(Money::zero(self.subtotal.currency()), |acc, i| acc.add(&i.total).unwrap_or(acc))
+    }
+    
+    pub fn tax(&self) -> &Money { &self.tax }
+    
+    /// Taxes the `taxable_subtotal` for the shipping destination (zero without one) and updates the order total.
+    pub fn apply_tax(&mut self, strategy: &dyn TaxStrategy) -> Money {
+        self.tax = match &self.shipping_address {
+            Some(destination) => strategy.tax_for(&self.taxable_subtotal(), destination),
+            None => Money::zero(self.subtotal.currency()),
+        };
+        self.recalculate();
+        self.tax.clone()
+    }
+    
+    pub fn total_weight(&self, unit: WeightUnit) -> f64 {
+        self.items.iter().filter_map(|i| i.weight.map(|w| i.weight_unit.convert(w, unit) * i.quantity as f64)).sum()
+    }
+    
+    /// Holds the order for manual stock allocation; it cannot ship until every line is allocated.
+    pub fn require_allocation(&mut self) { self.requires_allocation = true; self.touch(); }
+    pub fn requires_allocation(&self) -> bool { self.requires_allocation }
+    pub fn allocated_quantity(&self, line_id: &str) -> u32 { self.allocations.get(line_id).copied().unwrap_or(0) }
+    
+    pub fn allocate_line(&mut self, line_id: &str, quantity: u32) -> Result<(), OrderError> {
+        let line = self.items.iter().find(|i| i.id == line_id).ok_or(OrderError::ItemNotFound)?;
+        let allocated = self.allocated_quantity(line_id).checked_add(quantity).filter(|a| *a <= line.quantity).ok_or(OrderError::OverAllocated)?;
+        self.allocations.insert(line_id.to_string(), allocated);
+        self.touch();
+        Ok(())
+    }
+    
+    pub fn allocation_status(&self) -> AllocationStatus {
+        if !self.requires_allocation { return AllocationStatus::NotRequired; }
+        if self.items.iter().all(|i| self.allocated_quantity(&i.id) >= i.quantity) { AllocationStatus::Allocated } else { AllocationStatus::Awaiting }
+    }
+    
+    /// Confirms after re-checking stock: `available` maps a product id to its sellable quantity, or `None` for
+    /// preorder/backorder products that are not stock-limited.
+    pub fn confirm_with_stock(&mut self, available: impl Fn(&str) -> Option<u32>) -> Result<(), OrderError> {
+        for item in &self.items {
+            if let Some(stock) = available(&item.product_id).filter(|&stock| stock < item.quantity) {
+                return Err(OrderError::InsufficientInventory { line_id: item.id.clone(), requested: item.quantity, available: stock });
+            }
+        }
+        self.confirm()
+    }
+    
     pub fn confirm(&mut self) -> Result<(), OrderError> {
         if self.items.is_empty() { return Err(OrderError::NoItems); }
+        if !self.status.can_transition_to(&OrderStatus::Confirmed) { return Err(self.invalid_transition(OrderStatus::Confirmed)); }
         self.status = OrderStatus::Confirmed;
         self.touch();
         self.raise_event(DomainEvent::Order(OrderEvent::Confirmed { order_id: self.id.clone(), total: self.total.amount() }));
         Ok(())
     }
     
-    pub fn mark_paid(&mut self) { self.payment = PaymentStatus::Paid; self.status = OrderStatus::Processing; self.touch(); }
-    pub fn ship(&mut self) { self.status = OrderStatus::Shipped; self.fulfillment = FulfillmentStatus::Fulfilled; self.touch(); }
-    pub fn deliver(&mut self) { self.status = OrderStatus::Delivered; self.touch(); }
+    /// Authorizes the current order total; funds are captured later via `capture`. Only an unpaid, live order can be
+    /// authorized, so an existing authorization or payment is never overwritten.
+    pub fn authorize(&mut self) -> Result<(), OrderError> {
+        if self.payment != PaymentStatus::Pending || !matches!(self.status, OrderStatus::Pending | OrderStatus::Confirmed | OrderStatus::Processing) { return Err(OrderError::CannotAuthorize); }
+        self.authorized = self.total.clone();
+        self.payment = PaymentStatus::Authorized;
+        self.touch();
+        Ok(())
+    }
+    
+    pub fn capture(&mut self, amount: Money) -> Result<(), OrderError> {
+        if amount.amount() <= Decimal::ZERO { return Err(OrderError::NonPositiveAmount); }
+        if !matches!(self.payment, PaymentStatus::Authorized | PaymentStatus::PartiallyPaid) { return Err(OrderError::NotAuthorized); }
+        let captured = self.captured.add(&amount).map_err(|_| OrderError::CurrencyMismatch)?;
+        if captured.amount() > self.authorized.amount() { return Err(OrderError::CaptureExceedsAuthorized); }
+        self.payment = if captured.amount() == self.authorized.amount() { PaymentStatus::Paid } else { PaymentStatus::PartiallyPaid };
+        self.captured = captured;
+        self.touch();
+        Ok(())
+    }
+    
+    /// Releases an authorization; only allowed before any funds are captured.
+    pub fn void(&mut self) -> Result<(), OrderError> {
+        if self.payment != PaymentStatus::Authorized { return Err(OrderError::CannotVoid); }
+        self.payment = PaymentStatus::Voided;
+        self.touch();
+        Ok(())
+    }
+    
+    /// Records payment in full, capturing whatever is still authorized; moves a pending or confirmed order to
+    /// `Processing` and leaves later statuses as they are.
+    pub fn mark_paid(&mut self) -> Result<(), OrderError> {
+        if matches!(self.status, OrderStatus::Cancelled | OrderStatus::Refunded) { return Err(self.invalid_transition(OrderStatus::Processing)); }
+        if !matches!(self.payment, PaymentStatus::Pending | PaymentStatus::Authorized | PaymentStatus::PartiallyPaid) { return Err(OrderError::CannotMarkPaid); }
+        if self.authorized.amount() > Decimal::ZERO { self.captured = self.authorized.clone(); }
+        if self.status.can_transition_to(&OrderStatus::Processing) { self.status = OrderStatus::Processing; }
+        self.payment = PaymentStatus::Paid;
+        self.touch();
+        self.raise_event(DomainEvent::Order(OrderEvent::Paid { order_id: self.id.clone() }));
+        Ok(())
+    }
+    /// Records a refund; refunds accumulate until the refundable amount has been returned. Orders paid through
+    /// `authorize`/`capture` can only get back what was captured, and an authorization with nothing captured yet is
+    /// voided instead of refunded.
+    pub fn refund(&mut self, amount: Money) -> Result<(), OrderError> {
+        if amount.amount() <= Decimal::ZERO { return Err(OrderError::NonPositiveAmount); }
+        if self.payment == PaymentStatus::Authorized { return self.void(); }
+        if !matches!(self.payment, PaymentStatus::Paid | PaymentStatus::PartiallyPaid | PaymentStatus::PartiallyRefunded) { return Err(OrderError::NotPaid); }
+        let refunded = self.refunded.add(&amount).map_err(|_| OrderError::CurrencyMismatch)?;
+        let (limit, exceeded) = if self.authorized.amount() > Decimal::ZERO { (&self.captured, OrderError::RefundExceedsCaptured) } else { (&self.total, OrderError::RefundExceedsTotal) };
+        if refunded.amount() > limit.amount() { return Err(exceeded); }
+        self.payment = if refunded.amount() == limit.amount() { PaymentStatus::Refunded } else { PaymentStatus::PartiallyRefunded };
+        self.refunded = refunded;
+        self.touch();
+        self.raise_event(DomainEvent::Order(OrderEvent::Refunded { order_id: self.id.clone(), amount: amount.amount() }));
+        Ok(())
+    }
+    /// Ships the given lines in full, e.g. as a warehouse picks them.
+    pub fn fulfill_items(&mut self, line_item_ids: &[String]) -> Result<(), OrderError> {
+        self.check_shippable()?;
+        let shipments = line_item_ids.iter().map(|id| {
+            let item = self.items.iter().find(|i| &i.id == id).ok_or(OrderError::ItemNotFound)?;
+            Ok((id.clone(), self.unfulfilled_quantity(item)))
+        }).collect::<Result<Vec<_>, OrderError>>()?;
+        self.record_fulfillment(shipments);
+        Ok(())
+    }
+    
+    /// Ships what `available(product_id)` covers now; lines left short stay in `awaiting_stock` until restocked.
+    pub fn fulfill_available(&mut self, available: impl Fn(&str) -> u32) -> Result<(), OrderError> {
+        self.check_shippable()?;
+        let shipments: Vec<(String, u32)> = self.items.iter().map(|i| (i.id.clone(), self.unfulfilled_quantity(i).min(available(&i.product_id)))).collect();
+        self.record_fulfillment(shipments);
+        Ok(())
+    }
+    
+    /// Ships up to `quantity` restocked units of `product_id` to lines awaiting stock; returns how many were used.
+    /// Orders that have since been cancelled or refunded take nothing.
+    pub fn fulfill_restocked(&mut self, product_id: &str, quantity: u32) -> u32 {
+        if !self.awaits_restock() { return 0; }
+        let mut remaining = quantity;
+        let shipments: Vec<(String, u32)> = self.items.iter().filter(|i| i.product_id == product_id).map(|i| {
+            let qty = self.unfulfilled_quantity(i).min(remaining);
+            remaining -= qty;
+            (i.id.clone(), qty)
+        }).collect();
+        self.record_fulfillment(shipments);
+        quantity - remaining
+    }
+    
+    pub fn awaiting_stock(&self) -> Vec<&LineItem> {
+        if !self.awaits_restock() { return vec![]; }
+        self.items.iter().filter(|i| self.unfulfilled_quantity(i) > 0).collect()
+    }
+    
+    fn awaits_restock(&self) -> bool {
+        self.fulfillment == FulfillmentStatus::Partial && matches!(self.status, OrderStatus::Confirmed | OrderStatus::Processing | OrderStatus::Shipped)
+    }
+    
+    /// Shared by `ship` and the line-level fulfillment paths: only live orders ship, and never while held for allocation.
+    fn check_shippable(&self) -> Result<(), OrderError> {
+        if !matches!(self.status, OrderStatus::Confirmed | OrderStatus::Processing) { return Err(self.invalid_transition(OrderStatus::Shipped)); }
+        if self.allocation_status() == AllocationStatus::Awaiting { return Err(OrderError::AwaitingAllocation); }
+        Ok(())
+    }
+    
+    fn unfulfilled_quantity(&self, item: &LineItem) -> u32 { item.quantity.saturating_sub(self.fulfilled.get(&item.id).copied().unwrap_or(0)) }
+    
+    fn record_fulfillment(&mut self, shipments: Vec<(String, u32)>) {
+        let shipped: Vec<String> = shipments.iter().filter(|(_, qty)| *qty > 0).map(|(id, _)| id.clone()).collect();
+        for (line_id, qty) in shipments.into_iter().filter(|(_, qty)| *qty > 0) { *self.fulfilled.entry(line_id).or_insert(0) += qty; }
+        let done: Vec<bool> = self.items.iter().map(|i| self.unfulfilled_quantity(i) == 0).collect();
+        for (item, done) in self.items.iter_mut().zip(done) { item.fulfilled = done; }
+        if self.items.iter().all(|i| i.fulfilled) {
+            self.status = OrderStatus::Shipped;
+            self.fulfillment = FulfillmentStatus::Fulfilled;
+            self.raise_event(DomainEvent::Order(OrderEvent::Shipped { order_id: self.id.clone(), tracking: None }));
+        } else if !self.fulfilled.is_empty() {
+            self.fulfillment = FulfillmentStatus::Partial;
+            if !shipped.is_empty() { self.raise_event(DomainEvent::Order(OrderEvent::PartiallyFulfilled { order_id: self.id.clone(), line_item_ids: shipped })); }
+        }
+        self.touch();
+    }
+    
+    pub fn ship(&mut self, tracking: Option<String>) -> Result<(), OrderError> {
+        self.check_shippable()?;
+        self.status = OrderStatus::Shipped;
+        self.fulfillment = FulfillmentStatus::Fulfilled;
+        for item in &mut self.items { item.fulfilled = true; }
+        self.tracking_number = tracking.clone();
+        self.touch();
+        self.raise_event(DomainEvent::Order(OrderEvent::Shipped { order_id: self.id.clone(), tracking }));
+        Ok(())
+    }
+    pub fn deliver(&mut self) -> Result<(), OrderError> {
+        if !self.status.can_transition_to(&OrderStatus::Delivered) { return Err(self.invalid_transition(OrderStatus::Delivered)); }
+        self.status = OrderStatus::Delivered;
+        self.touch();
+        self.raise_event(DomainEvent::Order(OrderEvent::Delivered { order_id: self.id.clone() }));
+        Ok(())
+    }
     
     pub fn cancel(&mut self) -> Result<(), OrderError> {
-        if self.status == OrderStatus::Delivered { return Err(OrderError::CannotCancel); }
+        if !self.status.can_transition_to(&OrderStatus::Cancelled) { return Err(self.invalid_transition(OrderStatus::Cancelled)); }
         self.status = OrderStatus::Cancelled;
         self.touch();
         self.raise_event(DomainEvent::Order(OrderEvent::Cancelled { order_id: self.id.clone() }));
@@ -83,30 +446,497 @@ impl Order {
         self.touch();
     }
     
+    fn invalid_transition(&self, to: OrderStatus) -> OrderError { OrderError::InvalidTransition { from: self.status.clone(), to } }
     pub fn take_events(&mut self) -> Vec<DomainEvent> { std::mem::take(&mut self.events) }
     fn raise_event(&mut self, e: DomainEvent) { self.events.push(e); }
     fn touch(&mut self) { self.updated_at = Utc::now(); }
 }
 
-#[derive(Debug, Clone)] pub enum OrderError { NoItems, CannotCancel }
+#[derive(Debug, Clone)] pub enum OrderError { InsufficientInventory { line_id: String, requested: u32, available: u32 }, NoItems, CannotCancel, ItemNotFound, OverAllocated, AwaitingAllocation, NotAuthorized, CannotAuthorize, CannotMarkPaid, CaptureExceedsAuthorized, CannotVoid, CurrencyMismatch, GiftMessageTooLong, AlreadyFulfilled, RefundExceedsTotal, RefundExceedsCaptured, NotPaid, NonPositiveAmount, Locked, InvalidHistory, InvalidTransition { from: OrderStatus, to: OrderStatus } }
 impl std::error::Error for OrderError {}
 impl std::fmt::Display for OrderError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self { Self::NoItems => write!(f, "No items"), Self::CannotCancel => write!(f, "Cannot cancel") }
+        match self {
+            Self::InsufficientInventory { line_id, requested, available } => write!(f, "Insufficient inventory for line {}: requested {}, available {}", line_id, requested, available),
+            Self::NoItems => write!(f, "No items"),
+            Self::CannotCancel => write!(f, "Cannot cancel"),
+            Self::ItemNotFound => write!(f, "Item not found"),
+            Self::OverAllocated => write!(f, "Allocation exceeds line quantity"),
+            Self::AwaitingAllocation => write!(f, "Order awaiting allocation"),
+            Self::NotAuthorized => write!(f, "Payment not authorized"),
+            Self::CannotAuthorize => write!(f, "Payment cannot be authorized in its current state"),
+            Self::CannotMarkPaid => write!(f, "Payment cannot be marked paid in its current state"),
+            Self::CaptureExceedsAuthorized => write!(f, "Capture exceeds authorized amount"),
+            Self::CannotVoid => write!(f, "Cannot void payment"),
+            Self::CurrencyMismatch => write!(f, "Currency mismatch"),
+            Self::AlreadyFulfilled => write!(f, "Order already fulfilled"),
+            Self::GiftMessageTooLong => write!(f, "Gift message exceeds {} characters", MAX_GIFT_MESSAGE_LEN),
+            Self::RefundExceedsTotal => write!(f, "Refund exceeds refundable balance"),
+            Self::RefundExceedsCaptured => write!(f, "Refund exceeds captured amount"),
+            Self::NotPaid => write!(f, "Order has not been paid"),
+            Self::NonPositiveAmount => write!(f, "Amount must be positive"),
+            Self::InvalidHistory => write!(f, "Event history must start with a single Created event"),
+            Self::Locked => write!(f, "Order can no longer be edited"),
+            Self::InvalidTransition { from, to } => write!(f, "Invalid transition from {:?} to {:?}", from, to),
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::domain::aggregates::product::{Product, Variant};
+    use crate::domain::value_objects::{Quantity, Sku};
     #[test]
     fn test_order_workflow() {
         let mut order = Order::create(1001, "CUST001", "test@example.com", "USD");
-        order.add_item(LineItem { id: "1".into(), product_id: "P1".into(), name: "Widget".into(), sku: "W001".into(), quantity: 2, unit_price: Money::usd(Decimal::new(10, 0)), total: Money::usd(Decimal::new(20, 0)) });
+        order.add_item(LineItem { id: "1".into(), product_id: "P1".into(), name: "Widget".into(), sku: "W001".into(), quantity: 2, unit_price: Money::usd(Decimal::new(10, 0)), total: Money::usd(Decimal::new(20, 0)), ..Default::default() });
         order.confirm().unwrap();
         assert_eq!(order.status(), &OrderStatus::Confirmed);
-        order.mark_paid();
-        order.ship();
+        order.mark_paid().unwrap();
+        order.ship(None).unwrap();
         assert_eq!(order.status(), &OrderStatus::Shipped);
     }
+    
+    #[test]
+    fn test_total_weight_mixed_order() {
+        let mut order = Order::create(1003, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 2, weight: Some(1.5), weight_unit: WeightUnit::Kilograms, ..Default::default() });
+        order.add_item(LineItem { id: "2".into(), quantity: 1, weight: Some(500.0), weight_unit: WeightUnit::Grams, ..Default::default() });
+        order.add_item(LineItem { id: "3".into(), quantity: 4, weight: None, ..Default::default() });
+        assert_eq!(order.total_weight(WeightUnit::Kilograms), 3.5);
+    }
+    
+    #[test]
+    fn test_total_weight_from_variant_lines() {
+        let variant = |id: &str, weight| Variant { id: id.into(), sku: None, name: id.into(), price: Money::usd(Decimal::new(20, 0)), inventory: Quantity::new(10), weight: Some(weight), weight_unit: WeightUnit::Kilograms, image_ids: vec![], low_stock_threshold: None };
+        let mut product = Product::create(Sku::new("TEE").unwrap(), "Tee", Money::usd(Decimal::new(20, 0)));
+        product.add_variant(variant("S", 0.15));
+        product.add_variant(variant("XL", 0.3));
+        let mut order = Order::create(1003, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem::from_cart_item("1", &CartItem::from_product(&product, Some("S"), 2)));
+        order.add_item(LineItem::from_cart_item("2", &CartItem::from_product(&product, Some("XL"), 1)));
+        assert_eq!(order.total_weight(WeightUnit::Grams), 600.0);
+        assert_eq!(order.subtotal().amount(), Decimal::new(60, 0));
+    }
+    
+    #[test]
+    fn test_confirm_rechecks_stock() {
+        let mut order = Order::create(1008, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), product_id: "P1".into(), quantity: 3, ..Default::default() });
+        order.add_item(LineItem { id: "2".into(), product_id: "PRE".into(), quantity: 5, ..Default::default() });
+        let stock = |product_id: &str| if product_id == "P1" { Some(2) } else { None };
+        let err = order.confirm_with_stock(stock).unwrap_err();
+        assert!(matches!(err, OrderError::InsufficientInventory { ref line_id, requested: 3, available: 2 } if line_id == "1"));
+        assert_eq!(order.status(), &OrderStatus::Pending);
+        order.confirm_with_stock(|product_id| if product_id == "P1" { Some(3) } else { None }).unwrap();
+        assert_eq!(order.status(), &OrderStatus::Confirmed);
+    }
+    
+    #[test]
+    fn test_taxable_subtotal_skips_exempt_lines() {
+        let mut order = Order::create(1007, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, unit_price: Money::usd(Decimal::new(40, 0)), total: Money::usd(Decimal::new(40, 0)), ..Default::default() });
+        order.add_item(LineItem::gift_card("2", Money::usd(Decimal::new(25, 0))));
+        assert_eq!(order.taxable_subtotal().amount(), Decimal::new(40, 0));
+        assert_eq!(order.total().amount(), Decimal::new(65, 0));
+        
+        let tax = crate::domain::services::RegionalTax::new().with_rate("NG", Decimal::new(75, 3));
+        assert!(order.apply_tax(&tax).amount().is_zero());
+        order.set_shipping_address(Address { country: "NG".into(), ..Default::default() }, &tax).unwrap();
+        assert_eq!(order.tax().amount(), Decimal::new(300, 2));
+        assert_eq!(order.total().amount(), Decimal::new(6800, 2));
+    }
+    
+    #[test]
+    fn test_shipping_address_change() {
+        let mut order = Order::create(1006, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, ..Default::default() });
+        order.add_item(LineItem { id: "2".into(), quantity: 1, total: Money::usd(Decimal::new(100, 0)), ..Default::default() });
+        let tax = crate::domain::services::RegionalTax::new().with_rate("NG", Decimal::new(75, 3)).with_rate("GH", Decimal::new(15, 2));
+        let address = Address { name: "Ada".into(), street1: "1 Marina".into(), city: "Lagos".into(), zip: "101001".into(), country: "NG".into(), ..Default::default() };
+        order.set_shipping_address(address.clone(), &tax).unwrap();
+        assert_eq!(order.shipping_address().unwrap().city, "Lagos");
+        assert_eq!(order.tax().amount(), Decimal::new(750, 2));
+        order.set_shipping_address(Address { city: "Accra".into(), country: "GH".into(), ..address.clone() }, &tax).unwrap();
+        assert_eq!(order.tax().amount(), Decimal::new(1500, 2));
+        assert_eq!(order.total().amount(), Decimal::new(11500, 2));
+        order.confirm().unwrap();
+        order.ship(None).unwrap();
+        assert!(matches!(order.set_shipping_address(address, &tax), Err(OrderError::AlreadyFulfilled)));
+        assert_eq!(order.tax().amount(), Decimal::new(1500, 2));
+    }
+    
+    #[test]
+    fn test_gift_message() {
+        let mut order = Order::create(1005, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), name: "Widget".into(), sku: "W001".into(), quantity: 1, unit_price: Money::usd(Decimal::new(10, 0)), total: Money::usd(Decimal::new(10, 0)), ..Default::default() });
+        order.set_gift_message("Happy birthday!").unwrap();
+        assert!(order.is_gift());
+        assert!(order.packing_slip().contains("Gift message: Happy birthday!"));
+        assert!(!order.invoice().contains("Happy birthday!"));
+        assert!(matches!(order.set_gift_message("x".repeat(MAX_GIFT_MESSAGE_LEN + 1)), Err(OrderError::GiftMessageTooLong)));
+        assert_eq!(order.gift_message(), Some("Happy birthday!"));
+    }
+    
+    fn payable_order() -> Order {
+        let mut order = Order::create(1004, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 2, unit_price: Money::usd(Decimal::new(10, 0)), total: Money::usd(Decimal::new(20, 0)), ..Default::default() });
+        order.confirm().unwrap();
+        order.authorize().unwrap();
+        order
+    }
+    
+    #[test]
+    fn test_authorize_capture() {
+        let mut order = payable_order();
+        order.capture(Money::usd(Decimal::new(5, 0))).unwrap();
+        assert_eq!(order.payment_status(), &PaymentStatus::PartiallyPaid);
+        order.capture(Money::usd(Decimal::new(15, 0))).unwrap();
+        assert_eq!(order.payment_status(), &PaymentStatus::Paid);
+        assert!(matches!(order.void(), Err(OrderError::CannotVoid)));
+    }
+    
+    #[test]
+    fn test_capture_rejects_non_positive_amount() {
+        let mut order = payable_order();
+        order.capture(Money::usd(Decimal::new(20, 0))).unwrap();
+        assert!(matches!(order.capture(Money::usd(Decimal::new(-5, 0))), Err(OrderError::NonPositiveAmount)));
+        assert!(matches!(order.capture(Money::usd(Decimal::ZERO)), Err(OrderError::NonPositiveAmount)));
+        assert_eq!(order.payment_status(), &PaymentStatus::Paid);
+        assert_eq!(order.captured_total().amount(), Decimal::new(20, 0));
+    }
+    
+    #[test]
+    fn test_authorize_only_unpaid_live_orders() {
+        let mut order = payable_order();
+        assert!(matches!(order.authorize(), Err(OrderError::CannotAuthorize)));
+        order.capture(Money::usd(Decimal::new(20, 0))).unwrap();
+        assert!(matches!(order.authorize(), Err(OrderError::CannotAuthorize)));
+        
+        let mut voided = payable_order();
+        voided.void().unwrap();
+        assert!(matches!(voided.authorize(), Err(OrderError::CannotAuthorize)));
+        
+        let mut cancelled = Order::create(1020, "CUST001", "test@example.com", "USD");
+        cancelled.add_item(LineItem { id: "1".into(), quantity: 1, total: Money::usd(Decimal::new(10, 0)), ..Default::default() });
+        cancelled.cancel().unwrap();
+        assert!(matches!(cancelled.authorize(), Err(OrderError::CannotAuthorize)));
+        assert_eq!(cancelled.payment_status(), &PaymentStatus::Pending);
+    }
+    
+    #[test]
+    fn test_mark_paid_captures_authorization() {
+        let mut order = payable_order();
+        order.mark_paid().unwrap();
+        assert_eq!(order.captured_total().amount(), Decimal::new(20, 0));
+        order.refund(Money::usd(Decimal::new(20, 0))).unwrap();
+        assert_eq!(order.payment_status(), &PaymentStatus::Refunded);
+        assert!(matches!(order.mark_paid(), Err(OrderError::CannotMarkPaid)));
+        let mut voided = payable_order();
+        voided.void().unwrap();
+        assert!(matches!(voided.mark_paid(), Err(OrderError::CannotMarkPaid)));
+    }
+    
+    #[test]
+    fn test_authorize_void() {
+        let mut order = payable_order();
+        order.void().unwrap();
+        assert_eq!(order.payment_status(), &PaymentStatus::Voided);
+    }
+    
+    #[test]
+    fn test_refund_limited_to_captured() {
+        let mut order = payable_order();
+        order.capture(Money::usd(Decimal::new(15, 0))).unwrap();
+        order.refund(Money::usd(Decimal::new(10, 0))).unwrap();
+        assert_eq!(order.payment_status(), &PaymentStatus::PartiallyRefunded);
+        assert!(matches!(order.refund(Money::usd(Decimal::new(6, 0))), Err(OrderError::RefundExceedsCaptured)));
+        order.refund(Money::usd(Decimal::new(5, 0))).unwrap();
+        assert_eq!(order.payment_status(), &PaymentStatus::Refunded);
+        assert_eq!(order.refunded_total().amount(), Decimal::new(15, 0));
+    }
+    
+    #[test]
+    fn test_refund_uncaptured_voids() {
+        let mut order = payable_order();
+        order.take_events();
+        order.refund(Money::usd(Decimal::new(20, 0))).unwrap();
+        assert_eq!(order.payment_status(), &PaymentStatus::Voided);
+        assert_eq!(order.refunded_total().amount(), Decimal::ZERO);
+        assert!(order.take_events().is_empty());
+    }
+    
+    #[test]
+    fn test_over_capture_rejected() {
+        let mut order = payable_order();
+        assert!(matches!(order.capture(Money::usd(Decimal::new(21, 0))), Err(OrderError::CaptureExceedsAuthorized)));
+        assert_eq!(order.payment_status(), &PaymentStatus::Authorized);
+    }
+    
+    fn allocation_order() -> Order {
+        let mut order = Order::create(1002, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), product_id: "P1".into(), name: "Widget".into(), sku: "W001".into(), quantity: 2, unit_price: Money::usd(Decimal::new(10, 0)), total: Money::usd(Decimal::new(20, 0)), ..Default::default() });
+        order.add_item(LineItem { id: "2".into(), product_id: "P2".into(), name: "Gadget".into(), sku: "G001".into(), quantity: 1, unit_price: Money::usd(Decimal::new(5, 0)), total: Money::usd(Decimal::new(5, 0)), ..Default::default() });
+        order.require_allocation();
+        order.confirm().unwrap();
+        order
+    }
+    
+    #[test]
+    fn test_unallocated_order_cannot_ship() {
+        let mut order = allocation_order();
+        order.allocate_line("1", 2).unwrap();
+        assert_eq!(order.allocation_status(), AllocationStatus::Awaiting);
+        assert!(matches!(order.ship(None), Err(OrderError::AwaitingAllocation)));
+        assert!(matches!(order.allocate_line("2", 2), Err(OrderError::OverAllocated)));
+        assert!(matches!(order.allocate_line("1", u32::MAX), Err(OrderError::OverAllocated)));
+        assert_eq!(order.allocated_quantity("1"), 2);
+    }
+    
+    #[test]
+    fn test_allocated_order_ships() {
+        let mut order = allocation_order();
+        order.allocate_line("1", 1).unwrap();
+        order.allocate_line("1", 1).unwrap();
+        order.allocate_line("2", 1).unwrap();
+        assert_eq!(order.allocation_status(), AllocationStatus::Allocated);
+        order.ship(None).unwrap();
+        assert_eq!(order.status(), &OrderStatus::Shipped);
+    }
+    
+    #[test]
+    fn test_cannot_ship_cancelled_order() {
+        let mut order = Order::create(1010, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, ..Default::default() });
+        order.confirm().unwrap();
+        order.cancel().unwrap();
+        assert!(matches!(order.ship(None), Err(OrderError::InvalidTransition { from: OrderStatus::Cancelled, to: OrderStatus::Shipped })));
+        assert!(matches!(order.mark_paid(), Err(OrderError::InvalidTransition { from: OrderStatus::Cancelled, .. })));
+        assert_eq!(order.status(), &OrderStatus::Cancelled);
+    }
+    
+    #[test]
+    fn test_cancelled_order_cannot_be_reconfirmed_or_shipped() {
+        let mut order = Order::create(1015, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, ..Default::default() });
+        order.cancel().unwrap();
+        assert!(matches!(order.confirm(), Err(OrderError::InvalidTransition { from: OrderStatus::Cancelled, to: OrderStatus::Confirmed })));
+        assert!(matches!(order.ship(None), Err(OrderError::InvalidTransition { from: OrderStatus::Cancelled, to: OrderStatus::Shipped })));
+        assert_eq!(order.status(), &OrderStatus::Cancelled);
+        
+        let mut shipped = Order::create(1016, "CUST001", "test@example.com", "USD");
+        shipped.add_item(LineItem { id: "1".into(), quantity: 1, ..Default::default() });
+        shipped.confirm().unwrap();
+        assert!(matches!(shipped.confirm(), Err(OrderError::InvalidTransition { from: OrderStatus::Confirmed, to: OrderStatus::Confirmed })));
+        shipped.ship(None).unwrap();
+        assert!(matches!(shipped.cancel(), Err(OrderError::InvalidTransition { from: OrderStatus::Shipped, to: OrderStatus::Cancelled })));
+        assert_eq!(shipped.status(), &OrderStatus::Shipped);
+    }
+    
+    #[test]
+    fn test_cannot_deliver_unshipped_order() {
+        let mut order = Order::create(1011, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, ..Default::default() });
+        assert!(matches!(order.ship(None), Err(OrderError::InvalidTransition { from: OrderStatus::Pending, .. })));
+        order.confirm().unwrap();
+        assert!(matches!(order.deliver(), Err(OrderError::InvalidTransition { from: OrderStatus::Confirmed, to: OrderStatus::Delivered })));
+        order.ship(None).unwrap();
+        order.deliver().unwrap();
+        assert_eq!(order.status(), &OrderStatus::Delivered);
+    }
+    
+    #[test]
+    fn test_replay_lifecycle() {
+        let mut order = Order::create(1014, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, total: Money::usd(Decimal::new(10, 0)), ..Default::default() });
+        order.confirm().unwrap();
+        order.mark_paid().unwrap();
+        order.ship(Some("TRK-9".into())).unwrap();
+        let order_events = |order: &mut Order| -> Vec<OrderEvent> { order.take_events().into_iter().filter_map(|e| match e { DomainEvent::Order(e) => Some(e), _ => None }).collect() };
+        let history = order_events(&mut order);
+        let replayed = Order::replay(&history).unwrap();
+        assert_eq!(replayed.id(), order.id());
+        assert_eq!(replayed.status(), &OrderStatus::Shipped);
+        assert_eq!(replayed.payment_status(), &PaymentStatus::Paid);
+        assert_eq!(replayed.fulfillment_status(), &FulfillmentStatus::Fulfilled);
+        assert_eq!(replayed.tracking_number(), Some("TRK-9"));
+        assert!(replayed.events.is_empty());
+        
+        order.refund(Money::usd(Decimal::new(4, 0))).unwrap();
+        let partial = [history, order_events(&mut order)].concat();
+        let replayed = Order::replay(&partial).unwrap();
+        assert_eq!(replayed.payment_status(), &PaymentStatus::PartiallyRefunded);
+        assert_eq!(replayed.refunded_total().amount(), Decimal::new(4, 0));
+        order.refund(Money::usd(Decimal::new(6, 0))).unwrap();
+        let full = [partial, order_events(&mut order)].concat();
+        let replayed = Order::replay(&full).unwrap();
+        assert_eq!(replayed.payment_status(), order.payment_status());
+        assert_eq!(replayed.payment_status(), &PaymentStatus::Refunded);
+        assert_eq!(replayed.total().amount(), Decimal::new(10, 0));
+    }
+    
+    #[test]
+    fn test_replay_rejects_impossible_history() {
+        let created = OrderEvent::Created { order_id: "o-1".into(), customer_id: "c-1".into() };
+        let delivered = OrderEvent::Delivered { order_id: "o-1".into() };
+        assert!(matches!(Order::replay(&[created, delivered.clone()]), Err(OrderError::InvalidTransition { from: OrderStatus::Pending, to: OrderStatus::Delivered })));
+        assert!(matches!(Order::replay(&[delivered]), Err(OrderError::InvalidHistory)));
+        assert!(matches!(Order::replay(&[]), Err(OrderError::InvalidHistory)));
+    }
+    
+    #[test]
+    fn test_partial_then_full_refund() {
+        let mut order = Order::create(1012, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, total: Money::new(Decimal::new(100, 0), "USD"), ..Default::default() });
+        order.confirm().unwrap();
+        order.mark_paid().unwrap();
+        order.take_events();
+        order.refund(Money::new(Decimal::new(30, 0), "USD")).unwrap();
+        assert_eq!(order.payment_status(), &PaymentStatus::PartiallyRefunded);
+        assert!(matches!(order.refund(Money::new(Decimal::new(71, 0), "USD")), Err(OrderError::RefundExceedsTotal)));
+        order.refund(Money::new(Decimal::new(70, 0), "USD")).unwrap();
+        assert_eq!(order.payment_status(), &PaymentStatus::Refunded);
+        assert_eq!(order.refunded_total().amount(), Decimal::new(100, 0));
+        assert_eq!(order.take_events().len(), 2);
+    }
+    
+    #[test]
+    fn test_refund_requires_payment_and_positive_amount() {
+        let mut order = Order::create(1017, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, total: Money::usd(Decimal::new(100, 0)), ..Default::default() });
+        order.confirm().unwrap();
+        assert!(matches!(order.refund(Money::usd(Decimal::new(10, 0))), Err(OrderError::NotPaid)));
+        assert_eq!(order.payment_status(), &PaymentStatus::Pending);
+        order.mark_paid().unwrap();
+        order.take_events();
+        assert!(matches!(order.refund(Money::usd(Decimal::new(-10, 0))), Err(OrderError::NonPositiveAmount)));
+        assert!(matches!(order.refund(Money::usd(Decimal::ZERO)), Err(OrderError::NonPositiveAmount)));
+        assert_eq!(order.refunded_total().amount(), Decimal::ZERO);
+        assert!(order.take_events().is_empty());
+        order.refund(Money::usd(Decimal::new(100, 0))).unwrap();
+        assert!(matches!(order.refund(Money::usd(Decimal::new(1, 0))), Err(OrderError::NotPaid)));
+    }
+    
+    #[test]
+    fn test_edit_pending_order_lines() {
+        let mut order = Order::create(1013, "CUST001", "test@example.com", "USD");
+        let unit_price = Money::new(Decimal::new(10, 0), "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, unit_price: unit_price.clone(), total: unit_price.clone(), ..Default::default() });
+        order.add_item(LineItem { id: "2".into(), quantity: 1, unit_price: unit_price.clone(), total: unit_price, ..Default::default() });
+        order.update_item_quantity("1", 3).unwrap();
+        assert_eq!(order.total().amount(), Decimal::new(40, 0));
+        order.remove_item("2").unwrap();
+        assert_eq!(order.total().amount(), Decimal::new(30, 0));
+        assert!(matches!(order.remove_item("2"), Err(OrderError::ItemNotFound)));
+    }
+    
+    #[test]
+    fn test_confirmed_order_is_locked() {
+        let mut order = Order::create(1014, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, ..Default::default() });
+        order.confirm().unwrap();
+        assert!(matches!(order.update_item_quantity("1", 2), Err(OrderError::Locked)));
+        assert!(matches!(order.remove_item("1"), Err(OrderError::Locked)));
+        assert_eq!(order.items().len(), 1);
+    }
+    
+    #[test]
+    fn test_lifecycle_events() {
+        let mut order = Order::create(1015, "CUST001", "test@example.com", "USD");
+        assert!(matches!(order.take_events().as_slice(), [DomainEvent::Order(OrderEvent::Created { customer_id, .. })] if customer_id == "CUST001"));
+        order.add_item(LineItem { id: "1".into(), quantity: 1, ..Default::default() });
+        order.confirm().unwrap();
+        order.mark_paid().unwrap();
+        order.ship(None).unwrap();
+        order.deliver().unwrap();
+        let events = order.take_events();
+        assert!(matches!(events.as_slice(), [
+            DomainEvent::Order(OrderEvent::Confirmed { .. }),
+            DomainEvent::Order(OrderEvent::Paid { .. }),
+            DomainEvent::Order(OrderEvent::Shipped { .. }),
+            DomainEvent::Order(OrderEvent::Delivered { .. }),
+        ]));
+    }
+    
+    #[test]
+    fn test_backordered_lines_ship_after_restock() {
+        let mut order = Order::create(1016, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), product_id: "P1".into(), quantity: 2, ..Default::default() });
+        order.add_item(LineItem { id: "2".into(), product_id: "P2".into(), quantity: 3, ..Default::default() });
+        order.confirm().unwrap();
+        order.fulfill_available(|product_id| if product_id == "P1" { 5 } else { 1 }).unwrap();
+        assert_eq!(order.fulfillment_status(), &FulfillmentStatus::Partial);
+        assert_eq!(order.awaiting_stock().iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["2"]);
+        assert_eq!(order.fulfill_restocked("P2", 10), 2);
+        assert_eq!(order.fulfillment_status(), &FulfillmentStatus::Fulfilled);
+        assert_eq!(order.status(), &OrderStatus::Shipped);
+        assert!(order.awaiting_stock().is_empty());
+    }
+    
+    #[test]
+    fn test_cancelled_backorder_does_not_ship_on_restock() {
+        let mut order = Order::create(1019, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), product_id: "P1".into(), quantity: 2, ..Default::default() });
+        order.add_item(LineItem { id: "2".into(), product_id: "P2".into(), quantity: 3, ..Default::default() });
+        order.confirm().unwrap();
+        order.fulfill_available(|product_id| if product_id == "P1" { 5 } else { 0 }).unwrap();
+        order.cancel().unwrap();
+        assert!(order.awaiting_stock().is_empty());
+        assert_eq!(order.fulfill_restocked("P2", 10), 0);
+        assert_eq!(order.status(), &OrderStatus::Cancelled);
+        assert_eq!(order.fulfillment_status(), &FulfillmentStatus::Partial);
+    }
+    
+    #[test]
+    fn test_ship_with_tracking_number() {
+        let mut order = Order::create(1017, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 1, ..Default::default() });
+        order.confirm().unwrap();
+        order.take_events();
+        order.ship(Some("1Z999AA10123456784".into())).unwrap();
+        assert_eq!(order.tracking_number(), Some("1Z999AA10123456784"));
+        assert_eq!(order.fulfillment_status(), &FulfillmentStatus::Fulfilled);
+        assert!(matches!(order.take_events().as_slice(), [DomainEvent::Order(OrderEvent::Shipped { tracking: Some(t), .. })] if t == "1Z999AA10123456784"));
+    }
+    
+    #[test]
+    fn test_fulfill_items_by_line() {
+        let mut order = Order::create(1018, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), quantity: 2, ..Default::default() });
+        order.add_item(LineItem { id: "2".into(), quantity: 1, ..Default::default() });
+        order.confirm().unwrap();
+        order.take_events();
+        order.fulfill_items(&["1".to_string()]).unwrap();
+        assert_eq!(order.fulfillment_status(), &FulfillmentStatus::Partial);
+        assert!(order.items()[0].fulfilled && !order.items()[1].fulfilled);
+        assert!(matches!(order.fulfill_items(&["9".to_string()]), Err(OrderError::ItemNotFound)));
+        order.fulfill_items(&["2".to_string()]).unwrap();
+        assert_eq!(order.fulfillment_status(), &FulfillmentStatus::Fulfilled);
+        assert!(matches!(order.take_events().as_slice(), [
+            DomainEvent::Order(OrderEvent::PartiallyFulfilled { .. }),
+            DomainEvent::Order(OrderEvent::Shipped { .. }),
+        ]));
+    }
+    
+    #[test]
+    fn test_line_fulfillment_respects_allocation_hold() {
+        let mut order = allocation_order();
+        assert!(matches!(order.fulfill_items(&["1".to_string()]), Err(OrderError::AwaitingAllocation)));
+        assert!(matches!(order.fulfill_available(|_| 10), Err(OrderError::AwaitingAllocation)));
+        assert_eq!(order.fulfillment_status(), &FulfillmentStatus::Unfulfilled);
+        order.allocate_line("1", 2).unwrap();
+        order.allocate_line("2", 1).unwrap();
+        order.fulfill_items(&["1".to_string()]).unwrap();
+        assert_eq!(order.fulfillment_status(), &FulfillmentStatus::Partial);
+    }
+    
+    #[test]
+    fn test_fulfillment_sla_uses_business_days() {
+        use chrono::TimeZone;
+        let mut order = Order::create(1019, "CUST001", "test@example.com", "USD");
+        let friday = Utc.with_ymd_and_hms(2024, 11, 29, 9, 0, 0).unwrap();
+        order.set_fulfillment_sla(friday, 2, &[]);
+        assert_eq!(order.fulfillment_due_at(), Some(Utc.with_ymd_and_hms(2024, 12, 3, 9, 0, 0).unwrap()));
+    }
 }
@@ -2,8 +2,9 @@
 
 use chrono::{DateTime, Utc};
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
-use crate::domain::value_objects::{Sku, Money, Quantity};
+use crate::domain::value_objects::{Sku, Money, Quantity, WeightUnit, slugify};
 use crate::domain::events::{DomainEvent, ProductEvent};
 
 #[derive(Clone, Debug)]
@@ -11,11 +12,19 @@ pub struct Product {
     id: String,
     sku: Sku,
     name: String,
+    handle: String,
     description: String,
     price: Money,
     compare_at_price: Option<Money>,
     cost: Option<Money>,
     inventory: Quantity,
+    reserved: Quantity,
+    low_stock_threshold: Option<u32>,
+    max_per_order: Option<u32>,
+    inventory_policy: InventoryPolicy,
+    preorder_until: Option<DateTime<Utc>>,
+    weight: Option<f64>,
+    weight_unit: WeightUnit,
     status: ProductStatus,
     categories: Vec<String>,
     tags: Vec<String>,
@@ -26,17 +35,43 @@ pub struct Product {
     events: Vec<DomainEvent>,
 }
 
-#[derive(Clone, Debug)] pub struct Variant { pub id: String, pub sku: Option<Sku>, pub name: String, pub price: Money, pub inventory: Quantity }
-#[derive(Clone, Debug)] pub struct ProductImage { pub url: String, pub alt: Option<String>, pub position: u32 }
+#[derive(Clone, Debug)] pub struct Variant { pub id: String, pub sku: Option<Sku>, pub name: String, pub price: Money, pub inventory: Quantity, pub weight: Option<f64>, pub weight_unit: WeightUnit, pub image_ids: Vec<String>, pub low_stock_threshold: Option<u32> }
+#[derive(Clone, Debug)] pub struct ProductImage { pub id: String, pub url: String, pub alt: Option<String>, pub position: u32 }
 #[derive(Clone, Debug, Default, PartialEq, Eq)] pub enum ProductStatus { #[default] Draft, Active, Archived }
+/// Whether the product can still be sold once inventory hits zero
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)] pub enum InventoryPolicy { #[default] Deny, Continue }
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)] #[serde(rename_all = "snake_case")] pub enum StockStatus { InStock, LowStock, OutOfStock, Backorder, Preorder }
+
+impl StockStatus {
+    /// Classifies `available` units; an upcoming `preorder_until` release wins over stock levels.
+    pub fn classify(available: u32, policy: InventoryPolicy, low_stock_threshold: Option<u32>, preorder_until: Option<DateTime<Utc>>, now: DateTime<Utc>) -> Self {
+        if preorder_until.is_some_and(|release| release > now) { return Self::Preorder; }
+        match (available, policy) {
+            (0, InventoryPolicy::Continue) => Self::Backorder,
+            (0, InventoryPolicy::Deny) => Self::OutOfStock,
+            _ if low_stock_threshold.is_some_and(|t| available <= t) => Self::LowStock,
+            _ => Self::InStock,
+        }
+    }
+}
+
+/// Listing fields `publish_with` insists on besides the name; the default requires all of them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PublishRequirements { pub price: bool, pub images: bool, pub description: bool }
+
+impl Default for PublishRequirements {
+    fn default() -> Self { Self { price: true, images: true, description: true } }
+}
 
 impl Product {
     pub fn create(sku: Sku, name: impl Into<String>, price: Money) -> Self {
+        let name = name.into();
         let id = Uuid::new_v4().to_string();
         let now = Utc::now();
         let mut product = Self {
-            id: id.clone(), sku: sku.clone(), name: name.into(), description: String::new(),
-            price, compare_at_price: None, cost: None, inventory: Quantity::default(),
+            id: id.clone(), sku: sku.clone(), handle: slugify(&name), name, description: String::new(),
+            price, compare_at_price: None, cost: None, inventory: Quantity::default(), reserved: Quantity::default(),
+            low_stock_threshold: None, max_per_order: None, inventory_policy: InventoryPolicy::default(), preorder_until: None, weight: None, weight_unit: WeightUnit::default(),
             status: ProductStatus::Draft, categories: vec![], tags: vec![], variants: vec![],
             images: vec![], created_at: now, updated_at: now, events: vec![],
         };
@@ -47,13 +82,101 @@ impl Product {
     pub fn id(&self) -> &str { &self.id }
     pub fn sku(&self) -> &Sku { &self.sku }
     pub fn name(&self) -> &str { &self.name }
+    /// URL handle; generated from the name at creation unless set explicitly.
+    pub fn handle(&self) -> &str { &self.handle }
+    pub fn set_handle(&mut self, handle: &str) { self.handle = slugify(handle); self.touch(); }
     pub fn price(&self) -> &Money { &self.price }
+    pub fn description(&self) -> &str { &self.description }
+    pub fn set_description(&mut self, description: impl Into<String>) { self.description = description.into(); self.touch(); }
     pub fn inventory(&self) -> &Quantity { &self.inventory }
     pub fn status(&self) -> &ProductStatus { &self.status }
-    pub fn is_in_stock(&self) -> bool { !self.inventory.is_zero() }
+    pub fn is_in_stock(&self) -> bool { self.available() > 0 }
+    /// On-hand inventory not held by checkout reservations
+    pub fn available(&self) -> u32 { self.inventory.value().saturating_sub(self.reserved.value()) }
+    pub fn variants(&self) -> &[Variant] { &self.variants }
+    pub fn categories(&self) -> &[String] { &self.categories }
+    
+    /// Makes `category_id` the primary (first) category, raising `CategoryChanged` so counts and caches can follow.
+    pub fn assign_category(&mut self, category_id: Option<String>) {
+        let from = self.categories.first().cloned();
+        if from == category_id { return; }
+        match &category_id {
+            Some(to) if self.categories.is_empty() => self.categories.push(to.clone()),
+            Some(to) => self.categories[0] = to.clone(),
+            None => { self.categories.remove(0); }
+        }
+        self.touch();
+        self.raise_event(DomainEvent::Product(ProductEvent::CategoryChanged { product_id: self.id.clone(), from, to: category_id }));
+    }
+    
+    pub fn set_low_stock_threshold(&mut self, threshold: Option<u32>) { self.low_stock_threshold = threshold; self.touch(); }
+    /// Most units of this product a single cart/order may hold; `None` is unlimited.
+    pub fn max_per_order(&self) -> Option<u32> { self.max_per_order }
+    pub fn set_max_per_order(&mut self, max: Option<u32>) { self.max_per_order = max; self.touch(); }
+    pub fn set_inventory_policy(&mut self, policy: InventoryPolicy) { self.inventory_policy = policy; self.touch(); }
+    pub fn set_preorder_until(&mut self, release: Option<DateTime<Utc>>) { self.preorder_until = release; self.touch(); }
+    
+    /// Single source of truth for storefront stock badges; an upcoming preorder release wins over inventory.
+    pub fn stock_status(&self) -> StockStatus {
+        StockStatus::classify(self.available(), self.inventory_policy, self.low_stock_threshold, self.preorder_until, Utc::now())
+    }
+    pub fn images(&self) -> &[ProductImage] { &self.images }
+    pub fn primary_image(&self) -> Option<&ProductImage> { self.images.first() }
+    
+    /// Replaces the gallery, assigning positions in submission order and ignoring client-sent positions.
+    pub fn set_images(&mut self, images: Vec<ProductImage>) {
+        self.images = images.into_iter().enumerate().map(|(i, img)| ProductImage { position: i as u32, ..img }).collect();
+        self.touch();
+    }
+    
+    pub fn add_variant(&mut self, variant: Variant) { self.variants.push(variant); self.touch(); }
+    
+    /// The variant's own images in product order, or every product image when it has none assigned.
+    pub fn images_for_variant(&self, variant_id: &str) -> Vec<&ProductImage> {
+        let ids = self.variants.iter().find(|v| v.id == variant_id).map_or(&[][..], |v| &v.image_ids[..]);
+        let own: Vec<_> = self.images.iter().filter(|img| ids.contains(&img.id)).collect();
+        if own.is_empty() { self.images.iter().collect() } else { own }
+    }
+    
+    pub fn compare_at_price(&self) -> Option<&Money> { self.compare_at_price.as_ref() }
+    pub fn cost(&self) -> Option<&Money> { self.cost.as_ref() }
+    pub fn set_cost(&mut self, cost: Money) { self.cost = Some(cost); self.touch(); }
     
-    pub fn publish(&mut self) -> Result<(), ProductError> {
+    /// Price minus cost; `None` when cost is unset or in a different currency. May be negative.
+    pub fn margin(&self) -> Option<Money> {
+        self.price.subtract(self.cost.as_ref()?).ok()
+    }
+    
+    pub fn margin_percentage(&self) -> Option<Decimal> {
+        if self.price.amount().is_zero() { return None; }
+        self.margin().map(|m| (m.amount() / self.price.amount() * Decimal::ONE_HUNDRED).round_dp(2))
+    }
+    
+    pub fn has_negative_margin(&self) -> bool { self.margin().is_some_and(|m| m.amount().is_sign_negative()) }
+    
+    pub fn set_weight(&mut self, weight: f64, unit: WeightUnit) { self.weight = Some(weight); self.weight_unit = unit; self.touch(); }
+    
+    /// Unit shipping weight in `unit`, preferring the selected variant's weight over the product's.
+    pub fn weight_for(&self, variant_id: Option<&str>, unit: WeightUnit) -> Option<f64> {
+        let variant = variant_id.and_then(|id| self.variants.iter().find(|v| v.id == id));
+        match variant.and_then(|v| v.weight.map(|w| (w, v.weight_unit))) {
+            Some((w, from)) => Some(from.convert(w, unit)),
+            None => self.weight.map(|w| self.weight_unit.convert(w, unit)),
+        }
+    }
+    
+    /// Publishes with the default `PublishRequirements`.
+    pub fn publish(&mut self) -> Result<(), ProductError> { self.publish_with(PublishRequirements::default()) }
+    
+    /// Reports every missing field at once so an editor can fix the listing in one pass.
+    pub fn publish_with(&mut self, requirements: PublishRequirements) -> Result<(), ProductError> {
         if self.name.is_empty() { return Err(ProductError::MissingName); }
+        let missing: Vec<&'static str> = [
+            (requirements.price && self.price.amount() <= Decimal::ZERO, "price"),
+            (requirements.images && self.images.is_empty(), "images"),
+            (requirements.description && self.description.trim().is_empty(), "description"),
+        ].into_iter().filter_map(|(absent, field)| absent.then_some(field)).collect();
+        if !missing.is_empty() { return Err(ProductError::IncompletePublish { missing }); }
         self.status = ProductStatus::Active;
         self.touch();
         Ok(())
@@ -61,7 +184,15 @@ impl Product {
     
     pub fn archive(&mut self) { self.status = ProductStatus::Archived; self.touch(); }
     
-    pub fn update_price(&mut self, new_price: Money) {
+    /// With `auto_compare_at`, a price drop keeps the old price as the "was" price and a rise clears a compare-at it no longer undercuts.
+    pub fn update_price(&mut self, new_price: Money, auto_compare_at: bool) {
+        if auto_compare_at {
+            if new_price.currency() == self.price.currency() && new_price.amount() < self.price.amount() {
+                self.compare_at_price = Some(self.price.clone());
+            } else if self.compare_at_price.as_ref().is_some_and(|c| c.amount() <= new_price.amount()) {
+                self.compare_at_price = None;
+            }
+        }
         self.price = new_price;
         self.touch();
     }
@@ -72,22 +203,62 @@ impl Product {
         self.raise_event(DomainEvent::Product(ProductEvent::InventoryAdded { product_id: self.id.clone(), quantity: qty }));
     }
     
+    /// Also raises `LowStock` when the remaining quantity is at or below the low-stock threshold.
     pub fn remove_inventory(&mut self, qty: u32) -> Result<(), ProductError> {
         self.inventory = self.inventory.subtract(qty).ok_or(ProductError::InsufficientInventory)?;
         self.touch();
+        self.raise_event(DomainEvent::Product(ProductEvent::InventoryRemoved { product_id: self.id.clone(), quantity: qty }));
+        let remaining = self.inventory.value();
+        if self.low_stock_threshold.is_some_and(|t| remaining <= t) {
+            self.raise_event(DomainEvent::Product(ProductEvent::LowStock { product_id: self.id.clone(), variant_id: None, remaining }));
+        }
         Ok(())
     }
     
+    pub fn add_variant_inventory(&mut self, variant_id: &str, qty: u32) -> Result<(), ProductError> {
+        let variant = self.variants.iter_mut().find(|v| v.id == variant_id).ok_or(ProductError::VariantNotFound)?;
+        variant.inventory = variant.inventory.add(qty);
+        self.touch();
+        self.raise_event(DomainEvent::Product(ProductEvent::VariantInventoryAdded { product_id: self.id.clone(), variant_id: variant_id.to_string(), quantity: qty }));
+        Ok(())
+    }
+    
+    /// Raises `LowStock` against the variant's own threshold, falling back to the product-level one when unset.
+    pub fn remove_variant_inventory(&mut self, variant_id: &str, qty: u32) -> Result<(), ProductError> {
+        let variant = self.variants.iter_mut().find(|v| v.id == variant_id).ok_or(ProductError::VariantNotFound)?;
+        variant.inventory = variant.inventory.subtract(qty).ok_or(ProductError::InsufficientInventory)?;
+        let (remaining, threshold) = (variant.inventory.value(), variant.low_stock_threshold.or(self.low_stock_threshold));
+        self.touch();
+        self.raise_event(DomainEvent::Product(ProductEvent::VariantInventoryRemoved { product_id: self.id.clone(), variant_id: variant_id.to_string(), quantity: qty }));
+        if threshold.is_some_and(|t| remaining <= t) {
+            self.raise_event(DomainEvent::Product(ProductEvent::LowStock { product_id: self.id.clone(), variant_id: Some(variant_id.to_string()), remaining }));
+        }
+        Ok(())
+    }
+    
+    /// Holds stock for an in-progress checkout without removing it from inventory.
+    pub fn reserve(&mut self, qty: u32) -> Result<(), ProductError> {
+        if qty > self.available() { return Err(ProductError::InsufficientInventory); }
+        self.reserved = self.reserved.add(qty);
+        self.touch();
+        Ok(())
+    }
+    
+    pub fn release(&mut self, qty: u32) { self.reserved = Quantity::new(self.reserved.value().saturating_sub(qty)); self.touch(); }
+    
     pub fn take_events(&mut self) -> Vec<DomainEvent> { std::mem::take(&mut self.events) }
     fn raise_event(&mut self, e: DomainEvent) { self.events.push(e); }
     fn touch(&mut self) { self.updated_at = Utc::now(); }
 }
 
-#[derive(Debug, Clone)] pub enum ProductError { MissingName, InsufficientInventory }
+#[derive(Debug, Clone)] pub enum ProductError { MissingName, InsufficientInventory, VariantNotFound, IncompletePublish { missing: Vec<&'static str> } }
 impl std::error::Error for ProductError {}
 impl std::fmt::Display for ProductError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self { Self::MissingName => write!(f, "Missing name"), Self::InsufficientInventory => write!(f, "Insufficient inventory") }
+        match self {
+            Self::MissingName => write!(f, "Missing name"), Self::InsufficientInventory => write!(f, "Insufficient inventory"), Self::VariantNotFound => write!(f, "Variant not found"),
+            Self::IncompletePublish { missing } => write!(f, "Cannot publish, missing: {}", missing.join(", ")),
+        }
     }
 }
 
@@ -95,11 +266,45 @@ impl std::fmt::Display for ProductError {
 mod tests {
     use super::*;
     #[test]
+    fn test_publish_requires_complete_listing() {
+        let mut p = Product::create(Sku::new("PUB").unwrap(), "Lamp", Money::usd(Decimal::new(40, 0)));
+        p.set_description("Brass desk lamp");
+        assert!(matches!(p.publish(), Err(ProductError::IncompletePublish { missing }) if missing == ["images"]));
+        assert_eq!(p.status(), &ProductStatus::Draft);
+        p.publish_with(PublishRequirements { images: false, ..Default::default() }).unwrap();
+        assert_eq!(p.status(), &ProductStatus::Active);
+        
+        let mut p = Product::create(Sku::new("PUB2").unwrap(), "Lamp", Money::usd(Decimal::new(40, 0)));
+        p.set_description("Brass desk lamp");
+        p.set_images(vec![ProductImage { id: "front".into(), url: "front.jpg".into(), alt: None, position: 0 }]);
+        p.publish().unwrap();
+        assert_eq!(p.status(), &ProductStatus::Active);
+        let mut free = Product::create(Sku::new("PUB3").unwrap(), "Sticker", Money::usd(Decimal::ZERO));
+        assert!(matches!(free.publish(), Err(ProductError::IncompletePublish { missing }) if missing == ["price", "images", "description"]));
+    }
+    #[test]
     fn test_product_create() {
         let p = Product::create(Sku::new("TEST-001").unwrap(), "Test Product", Money::usd(Decimal::new(1999, 2)));
         assert_eq!(p.name(), "Test Product");
     }
     #[test]
+    fn test_handle_generated_from_name() {
+        let mut p = Product::create(Sku::new("MUG").unwrap(), "Café Latté Mug!", Money::usd(Decimal::new(12, 0)));
+        assert_eq!(p.handle(), "cafe-latte-mug");
+        p.set_handle("Morning Mug");
+        assert_eq!(p.handle(), "morning-mug");
+    }
+    #[test]
+    fn test_assign_category() {
+        let mut p = Product::create(Sku::new("CAT").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
+        p.assign_category(Some("shoes".into()));
+        p.take_events();
+        p.assign_category(Some("bags".into()));
+        p.assign_category(Some("bags".into()));
+        assert_eq!(p.categories(), ["bags"]);
+        assert!(matches!(p.take_events().as_slice(), [DomainEvent::Product(ProductEvent::CategoryChanged { from: Some(f), to: Some(t), .. })] if f == "shoes" && t == "bags"));
+    }
+    #[test]
     fn test_inventory() {
         let mut p = Product::create(Sku::new("TEST").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
         p.add_inventory(10);
@@ -107,4 +312,145 @@ mod tests {
         p.remove_inventory(5).unwrap();
         assert_eq!(p.inventory().value(), 5);
     }
+    #[test]
+    fn test_inventory_removed_event_only_on_success() {
+        let mut p = Product::create(Sku::new("REM").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
+        p.add_inventory(3);
+        p.take_events();
+        assert!(matches!(p.remove_inventory(5), Err(ProductError::InsufficientInventory)));
+        assert!(p.take_events().is_empty());
+        p.remove_inventory(2).unwrap();
+        assert!(matches!(p.take_events().as_slice(), [DomainEvent::Product(ProductEvent::InventoryRemoved { quantity: 2, .. })]));
+    }
+    #[test]
+    fn test_low_stock_event() {
+        let mut p = Product::create(Sku::new("LOW").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
+        p.set_low_stock_threshold(Some(3));
+        p.add_inventory(10);
+        p.remove_inventory(5).unwrap();
+        p.take_events();
+        p.remove_inventory(2).unwrap();
+        assert!(matches!(p.take_events().as_slice(), [
+            DomainEvent::Product(ProductEvent::InventoryRemoved { quantity: 2, .. }),
+            DomainEvent::Product(ProductEvent::LowStock { remaining: 3, .. }),
+        ]));
+    }
+    #[test]
+    fn test_variant_inventory() {
+        let mut p = Product::create(Sku::new("VAR").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
+        p.add_variant(Variant { id: "red".into(), sku: None, name: "Red".into(), price: Money::usd(Decimal::new(10, 0)), inventory: Quantity::new(2), weight: None, weight_unit: WeightUnit::default(), image_ids: vec![], low_stock_threshold: None });
+        p.take_events();
+        p.add_variant_inventory("red", 3).unwrap();
+        p.remove_variant_inventory("red", 4).unwrap();
+        assert_eq!(p.variants()[0].inventory.value(), 1);
+        assert!(matches!(p.remove_variant_inventory("red", 2), Err(ProductError::InsufficientInventory)));
+        assert!(matches!(p.add_variant_inventory("blue", 1), Err(ProductError::VariantNotFound)));
+        assert!(matches!(p.take_events().as_slice(), [
+            DomainEvent::Product(ProductEvent::VariantInventoryAdded { quantity: 3, .. }),
+            DomainEvent::Product(ProductEvent::VariantInventoryRemoved { variant_id, quantity: 4, .. }),
+        ] if variant_id == "red"));
+        assert_eq!(p.inventory().value(), 0);
+    }
+    #[test]
+    fn test_variant_low_stock_threshold() {
+        let mut p = Product::create(Sku::new("VLOW").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
+        p.set_low_stock_threshold(Some(2));
+        let variant = |id: &str, threshold| Variant { id: id.into(), sku: None, name: id.into(), price: Money::usd(Decimal::new(10, 0)), inventory: Quantity::new(10), weight: None, weight_unit: WeightUnit::default(), image_ids: vec![], low_stock_threshold: threshold };
+        p.add_variant(variant("xxs", Some(5)));
+        p.add_variant(variant("m", None));
+        p.take_events();
+        p.remove_variant_inventory("xxs", 6).unwrap();
+        p.remove_variant_inventory("m", 6).unwrap();
+        assert!(matches!(p.take_events().as_slice(), [
+            DomainEvent::Product(ProductEvent::VariantInventoryRemoved { .. }),
+            DomainEvent::Product(ProductEvent::LowStock { variant_id: Some(v), remaining: 4, .. }),
+            DomainEvent::Product(ProductEvent::VariantInventoryRemoved { .. }),
+        ] if v == "xxs"));
+        p.remove_variant_inventory("m", 2).unwrap();
+        assert!(matches!(p.take_events().as_slice(), [_, DomainEvent::Product(ProductEvent::LowStock { variant_id: Some(v), remaining: 2, .. })] if v == "m"));
+    }
+    #[test]
+    fn test_reservations() {
+        let mut p = Product::create(Sku::new("RES").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
+        p.add_inventory(5);
+        p.reserve(3).unwrap();
+        assert_eq!(p.available(), 2);
+        assert!(matches!(p.reserve(3), Err(ProductError::InsufficientInventory)));
+        p.reserve(2).unwrap();
+        assert!(!p.is_in_stock());
+        assert_eq!(p.inventory().value(), 5);
+        p.release(4);
+        assert_eq!(p.available(), 4);
+        assert!(p.is_in_stock());
+    }
+    #[test]
+    fn test_image_positions_follow_submission_order() {
+        let mut p = Product::create(Sku::new("IMG").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
+        let urls = ["front.jpg", "side.jpg", "back.jpg"];
+        p.set_images(urls.iter().zip([7, 0, 3]).map(|(u, pos)| ProductImage { id: u.to_string(), url: u.to_string(), alt: None, position: pos }).collect());
+        assert_eq!(p.images().iter().map(|i| i.position).collect::<Vec<_>>(), vec![0, 1, 2]);
+        assert_eq!(p.images().iter().map(|i| i.url.as_str()).collect::<Vec<_>>(), urls);
+        assert_eq!(p.primary_image().unwrap().url, "front.jpg");
+    }
+    #[test]
+    fn test_variant_images() {
+        let mut p = Product::create(Sku::new("VIMG").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
+        p.set_images(["red-front", "red-back", "blue-front"].iter().map(|id| ProductImage { id: id.to_string(), url: format!("{}.jpg", id), alt: None, position: 0 }).collect());
+        let variant = |id: &str, image_ids: &[&str]| Variant { id: id.into(), sku: None, name: id.into(), price: Money::usd(Decimal::new(10, 0)), inventory: Quantity::default(), weight: None, weight_unit: WeightUnit::default(), image_ids: image_ids.iter().map(|i| i.to_string()).collect(), low_stock_threshold: None };
+        p.add_variant(variant("red", &["red-back", "red-front"]));
+        p.add_variant(variant("green", &[]));
+        assert_eq!(p.images_for_variant("red").iter().map(|i| i.id.as_str()).collect::<Vec<_>>(), vec!["red-front", "red-back"]);
+        assert_eq!(p.images_for_variant("green").len(), 3);
+        assert_eq!(p.images_for_variant("missing").len(), 3);
+    }
+    #[test]
+    fn test_stock_status() {
+        let mut p = Product::create(Sku::new("SS").unwrap(), "P", Money::usd(Decimal::new(10, 0)));
+        p.set_low_stock_threshold(Some(5));
+        p.add_inventory(20);
+        assert_eq!(p.stock_status(), StockStatus::InStock);
+        p.remove_inventory(16).unwrap();
+        assert_eq!(p.stock_status(), StockStatus::LowStock);
+        p.remove_inventory(4).unwrap();
+        assert_eq!(p.stock_status(), StockStatus::OutOfStock);
+        p.set_inventory_policy(InventoryPolicy::Continue);
+        assert_eq!(p.stock_status(), StockStatus::Backorder);
+        p.set_preorder_until(Some(Utc::now() + chrono::Duration::days(30)));
+        assert_eq!(p.stock_status(), StockStatus::Preorder);
+    }
+    #[test]
+    fn test_auto_compare_at_price() {
+        let mut p = Product::create(Sku::new("TEE").unwrap(), "Tee", Money::usd(Decimal::new(30, 0)));
+        p.update_price(Money::usd(Decimal::new(20, 0)), true);
+        assert_eq!(p.compare_at_price(), Some(&Money::usd(Decimal::new(30, 0))));
+        p.update_price(Money::usd(Decimal::new(35, 0)), true);
+        assert_eq!(p.compare_at_price(), None);
+        p.update_price(Money::usd(Decimal::new(25, 0)), false);
+        assert_eq!(p.compare_at_price(), None);
+    }
+    #[test]
+    fn test_margin() {
+        let mut p = Product::create(Sku::new("MUG").unwrap(), "Mug", Money::usd(Decimal::new(2000, 2)));
+        assert_eq!(p.margin(), None);
+        p.set_cost(Money::usd(Decimal::new(1500, 2)));
+        assert_eq!(p.margin(), Some(Money::usd(Decimal::new(500, 2))));
+        assert_eq!(p.margin_percentage(), Some(Decimal::new(25, 0)));
+        assert!(!p.has_negative_margin());
+        p.set_cost(Money::usd(Decimal::new(2500, 2)));
+        assert!(p.has_negative_margin());
+        p.set_cost(Money::new(Decimal::new(10, 0), "EUR"));
+        assert_eq!(p.margin(), None);
+    }
+    #[test]
+    fn test_variant_weight() {
+        let mut p = Product::create(Sku::new("FLOUR").unwrap(), "Flour", Money::usd(Decimal::new(5, 0)));
+        p.set_weight(1.0, WeightUnit::Kilograms);
+        for (id, kg) in [("1KG", Some(1000.0)), ("5KG", Some(5000.0)), ("STD", None)] {
+            p.add_variant(Variant { id: id.into(), sku: None, name: id.into(), price: Money::usd(Decimal::new(5, 0)), inventory: Quantity::default(), weight: kg, weight_unit: WeightUnit::Grams, image_ids: vec![], low_stock_threshold: None });
+        }
+        assert_eq!(p.weight_for(Some("1KG"), WeightUnit::Kilograms), Some(1.0));
+        assert_eq!(p.weight_for(Some("5KG"), WeightUnit::Kilograms), Some(5.0));
+        assert_eq!(p.weight_for(Some("STD"), WeightUnit::Grams), Some(1000.0));
+        assert_eq!(p.weight_for(None, WeightUnit::Kilograms), Some(1.0));
+    }
 }
@@ -1,27 +1,61 @@
 //! Domain events
+//!
+//! Events serialize as flat JSON objects, e.g. `{"aggregate":"Product","type":"Created","product_id":"…","sku":"ABC"}`,
+//! so they can be stored in an outbox or published as-is.
 use crate::domain::value_objects::Sku;
 use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "aggregate")]
 pub enum DomainEvent {
     Product(ProductEvent),
     Order(OrderEvent),
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum ProductEvent {
     Created { product_id: String, sku: Sku },
     Published { product_id: String },
     InventoryAdded { product_id: String, quantity: u32 },
     InventoryRemoved { product_id: String, quantity: u32 },
+    VariantInventoryAdded { product_id: String, variant_id: String, quantity: u32 },
+    VariantInventoryRemoved { product_id: String, variant_id: String, quantity: u32 },
+    /// `variant_id` is set when a single variant ran low rather than the product as a whole.
+    LowStock { product_id: String, variant_id: Option<String>, remaining: u32 },
+    CategoryChanged { product_id: String, from: Option<String>, to: Option<String> },
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
 pub enum OrderEvent {
     Created { order_id: String, customer_id: String },
     Confirmed { order_id: String, total: Decimal },
     Paid { order_id: String },
+    PartiallyFulfilled { order_id: String, line_item_ids: Vec<String> },
     Shipped { order_id: String, tracking: Option<String> },
     Delivered { order_id: String },
     Cancelled { order_id: String },
+    Refunded { order_id: String, amount: Decimal },
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_event_json_round_trip() {
+        let event = ProductEvent::Created { product_id: "p-1".into(), sku: Sku::new("tee-01").unwrap() };
+        let json = serde_json::to_value(&event).unwrap();
+        assert_eq!(json, serde_json::json!({"type": "Created", "product_id": "p-1", "sku": "TEE-01"}));
+        assert_eq!(serde_json::from_value::<ProductEvent>(json).unwrap(), event);
+        let wrapped = DomainEvent::Order(OrderEvent::Refunded { order_id: "o-1".into(), amount: Decimal::new(1050, 2) });
+        let json = serde_json::to_string(&wrapped).unwrap();
+        assert!(json.contains(r#""aggregate":"Order","type":"Refunded""#));
+        assert_eq!(serde_json::from_str::<DomainEvent>(&json).unwrap(), wrapped);
+    }
+    #[test]
+    fn test_invalid_sku_is_rejected_on_deserialize() {
+        assert!(serde_json::from_value::<ProductEvent>(serde_json::json!({"type": "Created", "product_id": "p-1", "sku": ""})).is_err());
+    }
 }
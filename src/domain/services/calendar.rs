@@ -0,0 +1,34 @@
+//! Business-day arithmetic for fulfillment SLAs
+
+use chrono::{DateTime, Datelike, Duration, NaiveDate, Utc, Weekday};
+
+/// Moves `start` forward by `days` business days, skipping weekends and `holidays`; the time of day is kept.
+pub fn add_business_days(start: DateTime<Utc>, days: u32, holidays: &[NaiveDate]) -> DateTime<Utc> {
+    let mut due = start;
+    let mut remaining = days;
+    while remaining > 0 {
+        due += Duration::days(1);
+        if !matches!(due.weekday(), Weekday::Sat | Weekday::Sun) && !holidays.contains(&due.date_naive()) { remaining -= 1; }
+    }
+    due
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+
+    fn friday() -> DateTime<Utc> { Utc.with_ymd_and_hms(2024, 11, 29, 15, 0, 0).unwrap() }
+
+    #[test]
+    fn test_skips_weekend() {
+        assert_eq!(add_business_days(friday(), 2, &[]), Utc.with_ymd_and_hms(2024, 12, 3, 15, 0, 0).unwrap());
+    }
+
+    #[test]
+    fn test_skips_holidays() {
+        let holidays = [NaiveDate::from_ymd_opt(2024, 12, 2).unwrap()];
+        assert_eq!(add_business_days(friday(), 2, &holidays), Utc.with_ymd_and_hms(2024, 12, 4, 15, 0, 0).unwrap());
+        assert_eq!(add_business_days(friday(), 0, &holidays), friday());
+    }
+}
@@ -0,0 +1,36 @@
+//! Backorder fulfillment
+
+use crate::domain::aggregates::Order;
+use crate::domain::events::ProductEvent;
+
+/// Inventory event handler: hands restocked units to partially fulfilled orders in the order given (oldest first).
+pub fn fulfill_backorders(orders: &mut [Order], event: &ProductEvent) {
+    let ProductEvent::InventoryAdded { product_id, quantity } = event else { return };
+    let mut remaining = *quantity;
+    for order in orders.iter_mut() {
+        if remaining == 0 { break; }
+        remaining -= order.fulfill_restocked(product_id, remaining);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::domain::aggregates::{LineItem, FulfillmentStatus};
+
+    fn backordered(order_number: u64) -> Order {
+        let mut order = Order::create(order_number, "CUST001", "test@example.com", "USD");
+        order.add_item(LineItem { id: "1".into(), product_id: "P1".into(), quantity: 2, ..Default::default() });
+        order.confirm().unwrap();
+        order.fulfill_available(|_| 1).unwrap();
+        order
+    }
+
+    #[test]
+    fn test_restock_fills_oldest_backorder_first() {
+        let mut orders = vec![backordered(1), backordered(2)];
+        fulfill_backorders(&mut orders, &ProductEvent::InventoryAdded { product_id: "P1".into(), quantity: 1 });
+        assert_eq!(orders[0].fulfillment_status(), &FulfillmentStatus::Fulfilled);
+        assert_eq!(orders[1].fulfillment_status(), &FulfillmentStatus::Partial);
+    }
+}
@@ -0,0 +1,10 @@
+//! Domain services
+pub mod calendar;
+pub mod fulfillment;
+pub mod promotions;
+pub mod tax;
+
+pub use calendar::add_business_days;
+pub use fulfillment::fulfill_backorders;
+pub use promotions::FreeGift;
+pub use tax::{TaxStrategy, RegionalTax};
@@ -0,0 +1,56 @@
+//! Order promotions
+
+use crate::domain::aggregates::{LineItem, Order, OrderError, Product};
+use crate::domain::value_objects::Money;
+
+/// "Free tote bag with orders over $50": a zero-priced line for `product_id` while the order subtotal meets `threshold`.
+#[derive(Clone, Debug)]
+pub struct FreeGift { pub product_id: String, pub threshold: Money }
+
+impl FreeGift {
+    /// Adds or removes the gift line on a pending order to match its subtotal, taking or returning one unit of the
+    /// gift product's stock. An out-of-stock gift is simply not added.
+    pub fn apply(&self, order: &mut Order, gift: &mut Product) -> Result<(), OrderError> {
+        let line_id = format!("gift-{}", self.product_id);
+        let has_gift = order.items().iter().any(|i| i.id == line_id);
+        let qualifies = order.subtotal().currency() == self.threshold.currency() && order.subtotal().amount() >= self.threshold.amount();
+        if qualifies && !has_gift && gift.remove_inventory(1).is_ok() {
+            let unit_price = Money::zero(order.subtotal().currency());
+            order.add_item(LineItem { id: line_id, product_id: self.product_id.clone(), name: gift.name().to_string(), sku: gift.sku().as_str().to_string(), quantity: 1, total: unit_price.clone(), unit_price, ..Default::default() });
+        } else if !qualifies && has_gift {
+            order.remove_item(&line_id)?;
+            gift.add_inventory(1);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rust_decimal::Decimal;
+    use crate::domain::value_objects::Sku;
+
+    fn tote() -> Product {
+        let mut product = Product::create(Sku::new("TOTE-1").unwrap(), "Tote Bag", Money::usd(Decimal::new(15, 0)));
+        product.add_inventory(10);
+        product
+    }
+
+    #[test]
+    fn test_free_gift_follows_threshold() {
+        let promotion = FreeGift { product_id: "TOTE".into(), threshold: Money::usd(Decimal::new(50, 0)) };
+        let mut gift = tote();
+        let mut order = Order::create(2001, "CUST001", "test@example.com", "USD");
+        let unit_price = Money::usd(Decimal::new(30, 0));
+        order.add_item(LineItem { id: "1".into(), quantity: 2, unit_price: unit_price.clone(), total: unit_price.multiply(2), ..Default::default() });
+        promotion.apply(&mut order, &mut gift).unwrap();
+        assert!(order.items().iter().any(|i| i.id == "gift-TOTE" && i.total.amount().is_zero()));
+        assert_eq!(gift.inventory().value(), 9);
+        assert_eq!(order.total().amount(), Decimal::new(60, 0));
+        order.update_item_quantity("1", 1).unwrap();
+        promotion.apply(&mut order, &mut gift).unwrap();
+        assert!(!order.items().iter().any(|i| i.id == "gift-TOTE"));
+        assert_eq!(gift.inventory().value(), 10);
+    }
+}
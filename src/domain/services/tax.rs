@@ -0,0 +1,52 @@
+//! Tax calculation strategies
+
+use std::collections::HashMap;
+use rust_decimal::Decimal;
+use crate::domain::aggregates::Address;
+use crate::domain::value_objects::Money;
+
+/// Computes the tax owed on a taxable amount shipped to a destination
+pub trait TaxStrategy {
+    fn tax_for(&self, taxable: &Money, destination: &Address) -> Money;
+}
+
+/// Rates keyed by country (`"NG"`) or country-state (`"US-CA"`); the more specific region wins.
+#[derive(Clone, Debug, Default)]
+pub struct RegionalTax { rates: HashMap<String, Decimal> }
+
+impl RegionalTax {
+    pub fn new() -> Self { Self::default() }
+    pub fn with_rate(mut self, region: &str, rate: Decimal) -> Self { self.rates.insert(region.to_uppercase(), rate); self }
+    
+    fn rate_for(&self, destination: &Address) -> Option<Decimal> {
+        let country = destination.country.to_uppercase();
+        destination.state.as_ref()
+            .and_then(|state| self.rates.get(&format!("{}-{}", country, state.to_uppercase())))
+            .or_else(|| self.rates.get(&country))
+            .copied()
+    }
+}
+
+impl TaxStrategy for RegionalTax {
+    fn tax_for(&self, taxable: &Money, destination: &Address) -> Money {
+        match self.rate_for(destination) {
+            Some(rate) => Money::new((taxable.amount() * rate).round_dp(2), taxable.currency()),
+            None => Money::zero(taxable.currency()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_regional_rates() {
+        let tax = RegionalTax::new().with_rate("US", Decimal::new(5, 2)).with_rate("US-CA", Decimal::new(725, 4));
+        let amount = Money::usd(Decimal::new(100, 0));
+        let ca = Address { country: "US".into(), state: Some("ca".into()), ..Default::default() };
+        let ny = Address { country: "US".into(), state: Some("NY".into()), ..Default::default() };
+        assert_eq!(tax.tax_for(&amount, &ca).amount(), Decimal::new(725, 2));
+        assert_eq!(tax.tax_for(&amount, &ny).amount(), Decimal::new(5, 0));
+        assert!(tax.tax_for(&amount, &Address { country: "FR".into(), ..Default::default() }).amount().is_zero());
+    }
+}
@@ -2,10 +2,16 @@
 
 use rust_decimal::Decimal;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fmt;
 
+pub mod slug;
+pub use slug::slugify;
+
 /// SKU (Stock Keeping Unit) value object
+/// Serializes as the bare string; deserializing runs the same validation as `Sku::new`.
 #[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
 pub struct Sku(String);
 
 impl Sku {
@@ -18,6 +24,15 @@ impl Sku {
     pub fn as_str(&self) -> &str { &self.0 }
 }
 
+impl TryFrom<String> for Sku {
+    type Error = SkuError;
+    fn try_from(value: String) -> Result<Self, SkuError> { Self::new(value) }
+}
+
+impl From<Sku> for String {
+    fn from(sku: Sku) -> String { sku.0 }
+}
+
 impl fmt::Display for Sku {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "{}", self.0) }
 }
@@ -44,15 +59,107 @@ impl Money {
         if self.currency != other.currency { return Err(MoneyError::CurrencyMismatch); }
         Ok(Money::new(self.amount + other.amount, &self.currency))
     }
+    /// Subtracts `other`; the result may go negative (e.g. credit balances) rather than saturating at zero.
+    pub fn subtract(&self, other: &Money) -> Result<Money, MoneyError> {
+        if self.currency != other.currency { return Err(MoneyError::CurrencyMismatch); }
+        Ok(Money::new(self.amount - other.amount, &self.currency))
+    }
+    /// Converts into `target` using `rates`, rounded to the target currency's minor units.
+    pub fn convert_to(&self, target: &str, rates: &ExchangeRates) -> Result<Money, MoneyError> {
+        if self.currency == target { return Ok(self.clone()); }
+        let rate = rates.get(&self.currency, target).ok_or_else(|| MoneyError::NoRate { from: self.currency.clone(), to: target.to_string() })?;
+        Ok(Money::new((self.amount * rate).round_dp(minor_units(target)), target))
+    }
+    /// Rounds to the currency's minor units using banker's rounding (e.g. `19.995 USD` -> `20.00`, `19.985 USD` -> `19.98`).
+    pub fn round(&self) -> Money { Money::new(self.amount.round_dp(minor_units(&self.currency)), &self.currency) }
     pub fn multiply(&self, qty: u32) -> Money { Money::new(self.amount * Decimal::from(qty), &self.currency) }
+    /// Splits into `n` parts in minor units that sum exactly to the (rounded) amount; leftover minor units go to the
+    /// earliest parts, so $10 / 3 is $3.34, $3.33, $3.33.
+    pub fn split_into(&self, n: usize) -> Vec<Money> {
+        if n == 0 { return vec![]; }
+        let places = minor_units(&self.currency);
+        let total = self.round().amount;
+        let parts = Decimal::from(n as u64);
+        let base = (total / parts).round_dp_with_strategy(places, rust_decimal::RoundingStrategy::ToZero);
+        let unit = Decimal::new(if total.is_sign_negative() { -1 } else { 1 }, places);
+        let mut leftover = total - base * parts;
+        (0..n).map(|_| {
+            if leftover.is_zero() { return Money::new(base, &self.currency); }
+            leftover -= unit;
+            Money::new(base + unit, &self.currency)
+        }).collect()
+    }
+    
+    /// Storefront formatting per `format`: zero amounts render as `zero_label` (e.g. "Free") when one is configured.
+    pub fn format(&self, format: &MoneyFormat) -> String {
+        match &format.zero_label {
+            Some(label) if self.amount.is_zero() => label.clone(),
+            _ => self.format_locale(&format.locale),
+        }
+    }
+    
+    /// Formats the amount for a storefront locale (`en-US`, `de-DE`, `fr-FR`); unknown locales fall back to `en-US`.
+    pub fn format_locale(&self, locale: &str) -> String {
+        let (group, decimal, symbol_after) = match locale {
+            "de-DE" => (".", ",", true),
+            "fr-FR" => ("\u{a0}", ",", true),
+            _ => (",", ".", false),
+        };
+        let places = minor_units(&self.currency);
+        let rounded = self.amount.abs().round_dp(places);
+        let digits = format!("{:.*}", places as usize, rounded);
+        let (int_part, frac_part) = digits.split_once('.').unwrap_or((&digits, ""));
+        let mut grouped = String::new();
+        for (i, c) in int_part.chars().enumerate() {
+            if i > 0 && (int_part.len() - i) % 3 == 0 { grouped.push_str(group); }
+            grouped.push(c);
+        }
+        if !frac_part.is_empty() { grouped.push_str(decimal); grouped.push_str(frac_part); }
+        let sign = if self.amount.is_sign_negative() && !rounded.is_zero() { "-" } else { "" };
+        let symbol = currency_symbol(&self.currency);
+        if symbol_after { format!("{}{}\u{a0}{}", sign, grouped, symbol) }
+        else { format!("{}{}{}", sign, symbol, grouped) }
+    }
+}
+
+/// Storefront display settings for `Money::format`
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MoneyFormat { pub locale: String, pub zero_label: Option<String> }
+
+impl Default for MoneyFormat {
+    fn default() -> Self { Self { locale: "en-US".to_string(), zero_label: None } }
+}
+
+/// Decimal places used by `currency` (ISO 4217 minor units); unknown currencies default to 2.
+pub fn minor_units(currency: &str) -> u32 {
+    match currency { "JPY" | "KRW" => 0, "BHD" | "KWD" | "OMR" => 3, _ => 2 }
+}
+
+/// Exchange rates keyed by `(from, to)` currency pair
+#[derive(Clone, Debug, Default)]
+pub struct ExchangeRates { rates: HashMap<(String, String), Decimal> }
+
+impl ExchangeRates {
+    pub fn new() -> Self { Self::default() }
+    pub fn with_rate(mut self, from: &str, to: &str, rate: Decimal) -> Self { self.rates.insert((from.to_string(), to.to_string()), rate); self }
+    pub fn get(&self, from: &str, to: &str) -> Option<Decimal> { self.rates.get(&(from.to_string(), to.to_string())).copied() }
+}
+
+fn currency_symbol(currency: &str) -> &str {
+    match currency {
+        "USD" => "$", "EUR" => "€", "GBP" => "£", "NGN" => "₦", "JPY" => "¥",
+        other => other,
+    }
 }
 
 impl Default for Money { fn default() -> Self { Self::zero("USD") } }
 
-#[derive(Debug, Clone)] pub enum MoneyError { CurrencyMismatch }
+#[derive(Debug, Clone)] pub enum MoneyError { CurrencyMismatch, NoRate { from: String, to: String } }
 impl std::error::Error for MoneyError {}
 impl fmt::Display for MoneyError {
-    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result { write!(f, "Currency mismatch") }
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self { Self::CurrencyMismatch => write!(f, "Currency mismatch"), Self::NoRate { from, to } => write!(f, "No exchange rate from {} to {}", from, to) }
+    }
 }
 
 /// Quantity value object
@@ -71,6 +178,39 @@ impl Quantity {
 
 impl Default for Quantity { fn default() -> Self { Self(0) } }
 
+/// Discount code value object
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Discount { pub code: String, pub kind: DiscountKind }
+
+/// `Percentage` is expressed in percent (`10` = 10% off)
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum DiscountKind { Percentage(Decimal), FixedAmount(Money) }
+
+impl Discount {
+    /// Amount taken off `base`, never more than `base` itself.
+    pub fn amount_off(&self, base: &Money) -> Result<Money, MoneyError> {
+        let off = match &self.kind {
+            DiscountKind::Percentage(pct) => Money::new(base.amount() * pct / Decimal::ONE_HUNDRED, base.currency()).round(),
+            DiscountKind::FixedAmount(amount) => {
+                if amount.currency() != base.currency() { return Err(MoneyError::CurrencyMismatch); }
+                amount.clone()
+            }
+        };
+        Ok(if off.amount() > base.amount() { base.clone() } else { off })
+    }
+}
+
+/// Weight unit used for shipping calculations
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WeightUnit { Grams, #[default] Kilograms, Pounds, Ounces }
+
+impl WeightUnit {
+    fn grams_per_unit(&self) -> f64 {
+        match self { Self::Grams => 1.0, Self::Kilograms => 1000.0, Self::Pounds => 453.592_37, Self::Ounces => 28.349_523_125 }
+    }
+    pub fn convert(&self, value: f64, to: WeightUnit) -> f64 { value * self.grams_per_unit() / to.grams_per_unit() }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,4 +222,57 @@ mod tests {
         let b = Money::usd(Decimal::new(50, 0));
         assert_eq!(a.add(&b).unwrap().amount(), Decimal::new(150, 0));
     }
+    #[test]
+    fn test_money_subtract() {
+        let a = Money::usd(Decimal::new(100, 0));
+        let b = Money::usd(Decimal::new(30, 0));
+        assert_eq!(a.subtract(&b).unwrap().amount(), Decimal::new(70, 0));
+        assert_eq!(b.subtract(&a).unwrap().amount(), Decimal::new(-70, 0));
+        assert!(matches!(a.subtract(&Money::new(Decimal::ONE, "EUR")), Err(MoneyError::CurrencyMismatch)));
+    }
+    #[test]
+    fn test_round_minor_units() {
+        assert_eq!(minor_units("JPY"), 0);
+        assert_eq!(minor_units("BHD"), 3);
+        assert_eq!(Money::new(Decimal::new(19995, 3), "USD").round().amount(), Decimal::new(2000, 2));
+        assert_eq!(Money::new(Decimal::new(1985, 1), "JPY").round().amount(), Decimal::new(198, 0));
+        assert_eq!(Money::new(Decimal::new(12_3455, 4), "BHD").round().amount(), Decimal::new(12_346, 3));
+        assert_eq!(Money::new(Decimal::new(1234, 0), "JPY").format_locale("en-US"), "¥1,234");
+    }
+    #[test]
+    fn test_split_into() {
+        let parts = Money::usd(Decimal::new(10, 0)).split_into(3);
+        assert_eq!(parts.iter().map(|m| m.amount()).collect::<Vec<_>>(), vec![Decimal::new(334, 2), Decimal::new(333, 2), Decimal::new(333, 2)]);
+        assert_eq!(parts.iter().map(|m| m.amount()).sum::<Decimal>(), Decimal::new(10, 0));
+        assert_eq!(Money::new(Decimal::new(-100, 0), "JPY").split_into(3).iter().map(|m| m.amount()).collect::<Vec<_>>(), vec![Decimal::new(-34, 0), Decimal::new(-33, 0), Decimal::new(-33, 0)]);
+        assert!(Money::usd(Decimal::ONE).split_into(0).is_empty());
+    }
+    #[test]
+    fn test_zero_label() {
+        let free = MoneyFormat { zero_label: Some("Free".into()), ..MoneyFormat::default() };
+        assert_eq!(Money::zero("USD").format(&free), "Free");
+        assert_eq!(Money::zero("USD").format(&MoneyFormat::default()), "$0.00");
+        assert_eq!(Money::usd(Decimal::new(5, 0)).format(&free), "$5.00");
+    }
+    #[test]
+    fn test_convert_to() {
+        let rates = ExchangeRates::new().with_rate("NGN", "USD", Decimal::new(65, 5));
+        let converted = Money::new(Decimal::new(1_500_000, 2), "NGN").convert_to("USD", &rates).unwrap();
+        assert_eq!(converted, Money::usd(Decimal::new(975, 2)));
+        let err = Money::usd(Decimal::ONE).convert_to("NGN", &rates).unwrap_err();
+        assert!(matches!(err, MoneyError::NoRate { ref from, ref to } if from == "USD" && to == "NGN"));
+    }
+    #[test]
+    fn test_weight_convert() {
+        assert_eq!(WeightUnit::Grams.convert(1500.0, WeightUnit::Kilograms), 1.5);
+        assert!((WeightUnit::Pounds.convert(1.0, WeightUnit::Kilograms) - 0.453_592_37).abs() < 1e-9);
+    }
+    #[test]
+    fn test_format_locale() {
+        let m = Money::new(Decimal::new(123456, 2), "EUR");
+        assert_eq!(m.format_locale("en-US"), "€1,234.56");
+        assert_eq!(m.format_locale("de-DE"), "1.234,56\u{a0}€");
+        assert_eq!(m.format_locale("fr-FR"), "1\u{a0}234,56\u{a0}€");
+        assert_eq!(Money::usd(Decimal::new(-1234567, 1)).format_locale("xx-XX"), "-$123,456.70");
+    }
 }
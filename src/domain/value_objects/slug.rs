@@ -0,0 +1,49 @@
+//! URL handles
+
+/// Lowercase ASCII handle: accented Latin letters are transliterated, any other run of non-alphanumerics becomes a
+/// single hyphen, and leading/trailing hyphens are trimmed.
+pub fn slugify(input: &str) -> String {
+    let mut slug = String::with_capacity(input.len());
+    for c in input.chars().flat_map(char::to_lowercase) {
+        match transliterate(c) {
+            Some(ascii) => slug.push_str(ascii),
+            None if c.is_ascii_alphanumeric() => slug.push(c),
+            None => if !slug.is_empty() && !slug.ends_with('-') { slug.push('-') },
+        }
+    }
+    slug.trim_end_matches('-').to_string()
+}
+
+fn transliterate(c: char) -> Option<&'static str> {
+    Some(match c {
+        'à' | 'á' | 'â' | 'ã' | 'ä' | 'å' | 'ā' => "a",
+        'æ' => "ae",
+        'ç' | 'ć' | 'č' => "c",
+        'è' | 'é' | 'ê' | 'ë' | 'ē' | 'ę' | 'ě' => "e",
+        'ì' | 'í' | 'î' | 'ï' | 'ī' => "i",
+        'ñ' | 'ń' | 'ň' => "n",
+        'ò' | 'ó' | 'ô' | 'õ' | 'ö' | 'ø' | 'ō' => "o",
+        'œ' => "oe",
+        'ś' | 'š' => "s",
+        'ß' => "ss",
+        'ù' | 'ú' | 'û' | 'ü' | 'ū' | 'ů' => "u",
+        'ý' | 'ÿ' => "y",
+        'ź' | 'ż' | 'ž' => "z",
+        'ł' => "l",
+        'đ' | 'ð' => "d",
+        'þ' => "th",
+        _ => return None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    #[test]
+    fn test_slugify() {
+        assert_eq!(slugify("Café Latté Mug!"), "cafe-latte-mug");
+        assert_eq!(slugify("  Big   &&  Tall -- Tee  "), "big-tall-tee");
+        assert_eq!(slugify("Straße 42"), "strasse-42");
+        assert_eq!(slugify("!!!"), "");
+    }
+}
@@ -4,6 +4,7 @@
 
 pub mod domain;
 
-pub use domain::aggregates::{Product, Order, Cart, ProductError, OrderError, CartError};
-pub use domain::value_objects::{Sku, Money, Quantity};
+pub use domain::aggregates::{Product, Order, Cart, Address, ProductError, OrderError, CartError, QuantityLimit, InventoryPolicy, StockStatus};
+pub use domain::value_objects::{Sku, Money, MoneyFormat, Quantity, WeightUnit, ExchangeRates, Discount, DiscountKind, minor_units, slugify};
 pub use domain::events::{DomainEvent, ProductEvent, OrderEvent};
+pub use domain::services::{TaxStrategy, RegionalTax, FreeGift, fulfill_backorders, add_business_days};
@@ -1,26 +1,34 @@
 //! OpenSASE E-commerce - Self-hosted E-commerce Platform
 
 use anyhow::Result;
-use axum::{extract::{Path, Query, State}, http::StatusCode, response::IntoResponse, routing::{get, post, put, delete}, Json, Router};
+use axum::{extract::{Path, Query, State}, http::{header, HeaderMap, StatusCode}, routing::{get, post, put, delete}, Json, Router};
 use chrono::{DateTime, Utc};
 use serde::{Deserialize, Serialize};
 use sqlx::postgres::PgPoolOptions;
-use std::sync::Arc;
+use std::{collections::HashMap, sync::Arc};
 use tower_http::{cors::CorsLayer, trace::TraceLayer};
 use tracing_subscriber::{layer::SubscriberExt, util::SubscriberInitExt};
 use uuid::Uuid;
+use sase_ecommerce::{minor_units, slugify, Address, DomainEvent, InventoryPolicy, Money, OrderEvent, ProductEvent, QuantityLimit, RegionalTax, StockStatus, TaxStrategy};
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Product {
     pub id: Uuid, pub sku: String, pub name: String, pub description: Option<String>,
-    pub price: i64, pub compare_at_price: Option<i64>, pub currency: String,
-    pub category_id: Option<Uuid>, pub inventory_quantity: i32, pub status: String,
+    pub price: i64, pub compare_at_price: Option<i64>, pub cost: Option<i64>, pub currency: String,
+    pub category_id: Option<Uuid>, pub inventory_quantity: i32, pub max_per_order: Option<i32>, pub status: String,
+    /// `deny` or `continue` (keep selling at zero stock); a future `preorder_until` marks an upcoming release.
+    pub inventory_policy: String, pub preorder_until: Option<DateTime<Utc>>,
     pub images: Vec<String>, pub tags: Vec<String>, pub metadata: serde_json::Value,
     pub created_at: DateTime<Utc>, pub updated_at: DateTime<Utc>,
+    /// Computed when the product is presented, never stored.
+    #[sqlx(skip)] pub stock_status: Option<StockStatus>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
-pub struct Category { pub id: Uuid, pub name: String, pub slug: String, pub description: Option<String>, pub parent_id: Option<Uuid>, pub image_url: Option<String>, pub created_at: DateTime<Utc> }
+pub struct Category { pub id: Uuid, pub name: String, pub slug: String, pub description: Option<String>, pub parent_id: Option<Uuid>, pub image_url: Option<String>, pub created_at: DateTime<Utc>, #[sqlx(default)] pub product_count: i64 }
+
+/// Category columns plus a live count of its non-deleted products, so counts follow products as they move.
+const CATEGORY_WITH_COUNT: &str = "SELECT c.*, (SELECT COUNT(*) FROM products p WHERE p.category_id = c.id AND p.status <> 'deleted') AS product_count FROM categories c";
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Order {
@@ -31,13 +39,91 @@ pub struct Order {
     pub created_at: DateTime<Utc>, pub updated_at: DateTime<Utc>,
 }
 
+/// Display strings for an order's minor-unit amounts, e.g. `"₦1,320.00"`
+#[derive(Debug, Clone, Serialize)] pub struct FormattedTotals { pub subtotal: String, pub tax: String, pub shipping: String, pub total: String }
+/// Order as returned by the API: the raw row plus `formatted` amounts
+#[derive(Debug, Clone, Serialize)] pub struct OrderResponse { #[serde(flatten)] pub order: Order, pub formatted: FormattedTotals }
+
+fn format_minor(amount: i64, currency: &str) -> String { Money::new(rust_decimal::Decimal::new(amount, minor_units(currency)), currency).format_locale("en-US") }
+
+impl From<Order> for OrderResponse {
+    fn from(order: Order) -> Self {
+        let c = order.currency.as_str();
+        let formatted = FormattedTotals { subtotal: format_minor(order.subtotal, c), tax: format_minor(order.tax, c), shipping: format_minor(order.shipping, c), total: format_minor(order.total, c) };
+        Self { order, formatted }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct OrderItem { pub id: Uuid, pub order_id: Uuid, pub product_id: Uuid, pub sku: String, pub name: String, pub quantity: i32, pub unit_price: i64, pub total: i64 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
-pub struct CartItem { pub id: Uuid, pub session_id: String, pub product_id: Uuid, pub quantity: i32, pub created_at: DateTime<Utc> }
+pub struct CartItem { pub id: Uuid, pub session_id: String, pub product_id: Uuid, pub variant_id: Option<Uuid>, pub quantity: i32, pub unit_price: Option<i64>, pub created_at: DateTime<Utc> }
+
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct CustomerAddress { pub id: Uuid, pub customer_id: Uuid, pub address: serde_json::Value, pub is_default: bool, pub created_at: DateTime<Utc> }
+
+/// Sliding-window count of orders created per customer email, used to throttle runaway order creation.
+#[derive(Clone)] pub struct OrderRateLimiter { limit: usize, window: chrono::Duration, hits: Arc<dashmap::DashMap<String, Vec<DateTime<Utc>>>> }
+
+impl OrderRateLimiter {
+    pub fn new(limit: usize, window: chrono::Duration) -> Self { Self { limit, window, hits: Arc::new(dashmap::DashMap::new()) } }
+    /// Whether `email` has an attempt left in the current window, without using it up.
+    pub fn allows(&self, email: &str, now: DateTime<Utc>) -> bool {
+        self.hits.get(&email.trim().to_lowercase()).is_none_or(|hits| hits.iter().filter(|t| now - **t < self.window).count() < self.limit)
+    }
+    /// Counts an attempt against `email`; call once the order has actually gone through.
+    pub fn record(&self, email: &str, now: DateTime<Utc>) {
+        let mut hits = self.hits.entry(email.trim().to_lowercase()).or_default();
+        hits.retain(|t| now - *t < self.window);
+        hits.push(now);
+    }
+    /// Drops expired attempts and forgets emails with none left, so the map only holds live windows.
+    pub fn prune(&self, now: DateTime<Utc>) {
+        self.hits.retain(|_, hits| { hits.retain(|t| now - *t < self.window); !hits.is_empty() });
+    }
+}
+
+/// Fields a store requires at checkout: `email` is the customer email, any other name is a `shipping_address` key
+/// (e.g. `phone`). Defaults to just `email`.
+#[derive(Debug, Clone)] pub struct CheckoutFieldConfig { pub required: Vec<String> }
+
+impl Default for CheckoutFieldConfig {
+    fn default() -> Self { Self { required: vec!["email".to_string()] } }
+}
+
+impl CheckoutFieldConfig {
+    /// Parses a comma-separated list such as `email,phone`.
+    pub fn parse(fields: &str) -> Self { Self { required: fields.split(',').map(|f| f.trim().to_string()).filter(|f| !f.is_empty()).collect() } }
+    pub fn validate(&self, email: &str, address: &serde_json::Value) -> Result<(), (StatusCode, String)> {
+        let present = |field: &str| if field == "email" { !email.trim().is_empty() } else { address.get(field).and_then(|v| v.as_str()).is_some_and(|v| !v.trim().is_empty()) };
+        let missing: Vec<&str> = self.required.iter().map(String::as_str).filter(|f| !present(f)).collect();
+        if missing.is_empty() { Ok(()) } else { Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Missing required fields: {}", missing.join(", ")))) }
+    }
+}
+
+/// Parses `TAX_RATES` such as `NG=0.075,US-CA=0.0725`; malformed entries are skipped.
+fn parse_tax_rates(spec: &str) -> RegionalTax {
+    spec.split(',').filter_map(|entry| entry.split_once('=')).filter_map(|(region, rate)| Some((region.trim(), rate.trim().parse::<rust_decimal::Decimal>().ok()?)))
+        .fold(RegionalTax::new(), |tax, (region, rate)| tax.with_rate(region, rate))
+}
+
+/// Tax in minor units on `subtotal` for the `country`/`state` of a stored shipping address.
+fn destination_tax(tax: &RegionalTax, subtotal: i64, currency: &str, shipping_address: &serde_json::Value) -> i64 {
+    let field = |key: &str| shipping_address.get(key).and_then(|v| v.as_str()).map(str::to_string);
+    let destination = Address { country: field("country").unwrap_or_default(), state: field("state"), ..Default::default() };
+    let units = minor_units(currency);
+    let owed = tax.tax_for(&Money::new(rust_decimal::Decimal::new(subtotal, units), currency), &destination).amount();
+    i64::try_from((owed * rust_decimal::Decimal::from(10_i64.pow(units))).round()).unwrap_or(0)
+}
 
-#[derive(Clone)] pub struct AppState { pub db: sqlx::PgPool, pub nats: Option<async_nats::Client> }
+/// `hide_out_of_stock` drops zero-stock products that can't be sold (`deny` policy, no upcoming preorder) from listings;
+/// direct `get_product` lookups still return them.
+/// `auto_compare_at_price` keeps the previous price as `compare_at_price` when a product's price drops.
+/// `cart_quantity_limit` decides whether adds past a product's `max_per_order` are trimmed or refused.
+/// `image_cdn_base` rewrites product image URLs onto the CDN host in responses; stored URLs are left as they are.
+/// `tax` holds the per-destination rates applied to order subtotals.
+#[derive(Clone)] pub struct AppState { pub db: sqlx::PgPool, pub nats: Option<async_nats::Client>, pub hide_out_of_stock: bool, pub auto_compare_at_price: bool, pub order_limiter: OrderRateLimiter, pub image_cdn_base: Option<String>, pub image_cdn_params: Option<String>, pub checkout_fields: CheckoutFieldConfig, pub cart_quantity_limit: QuantityLimit, pub tax: RegionalTax, pub cart_ttl: chrono::Duration }
 
 #[tokio::main]
 async fn main() -> Result<()> {
@@ -46,17 +132,48 @@ async fn main() -> Result<()> {
     let db = PgPoolOptions::new().max_connections(10).connect(&std::env::var("DATABASE_URL")?).await?;
     sqlx::migrate!("./migrations").run(&db).await?;
     let nats = std::env::var("NATS_URL").ok().and_then(|url| futures::executor::block_on(async_nats::connect(&url)).ok());
-    let state = AppState { db, nats };
+    let hide_out_of_stock = std::env::var("HIDE_OUT_OF_STOCK").map(|v| v == "true" || v == "1").unwrap_or(false);
+    let auto_compare_at_price = std::env::var("AUTO_COMPARE_AT_PRICE").map(|v| v == "true" || v == "1").unwrap_or(false);
+    let orders_per_hour = std::env::var("ORDER_RATE_LIMIT_PER_HOUR").ok().and_then(|v| v.parse().ok()).unwrap_or(10);
+    let order_limiter = OrderRateLimiter::new(orders_per_hour, chrono::Duration::hours(1));
+    let image_cdn_base = std::env::var("IMAGE_CDN_BASE").ok().filter(|v| !v.is_empty());
+    let image_cdn_params = std::env::var("IMAGE_CDN_PARAMS").ok().filter(|v| !v.is_empty());
+    let checkout_fields = std::env::var("CHECKOUT_REQUIRED_FIELDS").map(|v| CheckoutFieldConfig::parse(&v)).unwrap_or_default();
+    let cart_quantity_limit = if std::env::var("CART_QUANTITY_LIMIT").is_ok_and(|v| v == "cap") { QuantityLimit::Cap } else { QuantityLimit::Reject };
+    let tax = std::env::var("TAX_RATES").map(|v| parse_tax_rates(&v)).unwrap_or_default();
+    let cart_ttl = chrono::Duration::hours(std::env::var("CART_TTL_HOURS").ok().and_then(|v| v.parse().ok()).unwrap_or(72));
+    let state = AppState { db, nats, hide_out_of_stock, auto_compare_at_price, order_limiter, image_cdn_base, image_cdn_params, checkout_fields, cart_quantity_limit, tax, cart_ttl };
+    let unpaid_timeout = chrono::Duration::hours(std::env::var("UNPAID_ORDER_TIMEOUT_HOURS").ok().and_then(|v| v.parse().ok()).unwrap_or(24));
+    tokio::spawn(cancel_stale_unpaid_task(state.clone(), unpaid_timeout));
+    tokio::spawn(prune_order_limiter_task(state.order_limiter.clone()));
 
     let app = Router::new()
         .route("/health", get(|| async { Json(serde_json::json!({"status": "healthy", "service": "opensase-ecommerce"})) }))
         .route("/api/v1/products", get(list_products).post(create_product))
         .route("/api/v1/products/:id", get(get_product).put(update_product).delete(delete_product))
+        .route("/api/v1/products/:id/restore", post(restore_product))
+        .route("/api/v1/products/:id/stock-take", post(stock_take))
+        .route("/api/v1/products/:id/stock-breakdown", get(get_stock_breakdown))
+        .route("/api/v1/reports/margins", get(margin_report))
         .route("/api/v1/categories", get(list_categories).post(create_category))
-        .route("/api/v1/categories/:id", get(get_category))
+        .route("/api/v1/categories/:id", get(get_category).put(update_category).delete(delete_category))
+        .route("/api/v1/categories/:id/breadcrumb", get(category_breadcrumb))
+        .route("/api/v1/categories/:id/products/order", put(reorder_category_products))
         .route("/api/v1/orders", get(list_orders).post(create_order))
         .route("/api/v1/orders/:id", get(get_order))
+        .route("/api/v1/orders/number/:number", get(get_order_by_number))
+        .route("/api/v1/orders/bulk-tag", post(bulk_tag_orders))
+        .route("/api/v1/orders/export-items.csv", get(export_order_items))
+        .route("/api/v1/orders/:id/verify", get(verify_order))
+        .route("/api/v1/orders/:id/shipping-address", put(update_shipping_address))
+        .route("/api/v1/orders/:id/payment", post(mark_order_paid))
+        .route("/api/v1/fulfillment/import", post(import_fulfillment))
+        .route("/api/v1/customers/:id/addresses", get(list_addresses).post(create_address))
+        .route("/api/v1/customers/:id/addresses/:address_id", put(update_address).delete(delete_address))
+        .route("/api/v1/customers/:id/addresses/:address_id/default", post(set_default_address))
         .route("/api/v1/cart/:session", get(get_cart).post(add_to_cart).delete(clear_cart))
+        .route("/api/v1/cart/:session/items/batch", post(add_to_cart_batch))
+        .route("/api/v1/cart/:session/validate", post(validate_cart))
         .route("/api/v1/checkout", post(checkout))
         .layer(TraceLayer::new_for_http()).layer(CorsLayer::permissive()).with_state(state);
 
@@ -66,36 +183,123 @@ async fn main() -> Result<()> {
     Ok(())
 }
 
-#[derive(Debug, Deserialize)] pub struct ListParams { pub page: Option<u32>, pub per_page: Option<u32>, pub category: Option<Uuid>, pub search: Option<String> }
-#[derive(Debug, Serialize)] pub struct PaginatedResponse<T> { pub data: Vec<T>, pub total: i64, pub page: u32 }
+#[derive(Debug, Deserialize)] pub struct ListParams { pub page: Option<u32>, pub per_page: Option<u32>, pub category: Option<Uuid>, pub search: Option<String>, pub sort: Option<String> }
+#[derive(Debug, Serialize)] pub struct PaginatedResponse<T> { pub data: Vec<T>, pub total: i64, pub page: u32, pub per_page: u32, pub total_pages: u32, pub has_next: bool }
 
-async fn list_products(State(s): State<AppState>, Query(p): Query<ListParams>) -> Result<Json<PaginatedResponse<Product>>, (StatusCode, String)> {
-    let page = p.page.unwrap_or(1).max(1); let per_page = p.per_page.unwrap_or(20).min(100);
-    let products = sqlx::query_as::<_, Product>("SELECT * FROM products WHERE status = 'active' ORDER BY created_at DESC LIMIT $1 OFFSET $2")
-        .bind(per_page as i64).bind(((page-1)*per_page) as i64).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM products WHERE status = 'active'").fetch_one(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(PaginatedResponse { data: products, total: total.0, page }))
+impl<T> PaginatedResponse<T> {
+    /// `per_page` is the effective (clamped, non-zero) page size the query ran with.
+    fn new(data: Vec<T>, total: i64, page: u32, per_page: u32) -> Self {
+        let total_pages = (total.max(0) as u64).div_ceil(per_page as u64) as u32;
+        Self { data, total, page, per_page, total_pages, has_next: page < total_pages }
+    }
+}
+
+/// Whitelisted `sort` values; the raw parameter never reaches SQL. Manual merchandising order only applies within a
+/// category, and anything unrecognised keeps newest-first.
+fn product_sort_clause(sort: Option<&str>, has_category: bool) -> &'static str {
+    match sort {
+        Some("manual") if has_category => "pc.sort_order ASC NULLS LAST, p.created_at DESC",
+        Some("price_asc") => "p.price ASC, p.created_at DESC",
+        Some("price_desc") => "p.price DESC, p.created_at DESC",
+        Some("name_asc") => "p.name ASC, p.created_at DESC",
+        Some("name_desc") => "p.name DESC, p.created_at DESC",
+        Some("created_asc") => "p.created_at ASC",
+        _ => "p.created_at DESC",
+    }
+}
+
+fn order_sort_clause(sort: Option<&str>) -> &'static str {
+    match sort {
+        Some("total_asc") => "total ASC, created_at DESC",
+        Some("total_desc") => "total DESC, created_at DESC",
+        Some("created_asc") => "created_at ASC",
+        _ => "created_at DESC",
+    }
+}
+
+/// Escapes LIKE wildcards so a search for `50%` matches literally; blank searches are ignored.
+fn search_term(search: &str) -> Option<String> {
+    let search = search.trim();
+    if search.is_empty() { return None; }
+    Some(search.replace('\\', "\\\\").replace('%', "\\%").replace('_', "\\_"))
+}
+
+/// Collects repeatable `attr=key:value` filters into parallel key/value arrays for the metadata match.
+fn attribute_filters(query: &[(String, String)]) -> Result<(Vec<String>, Vec<String>), (StatusCode, String)> {
+    query.iter().filter(|(k, _)| k == "attr").map(|(_, v)| match v.split_once(':') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_string(), value.to_string())),
+        _ => Err((StatusCode::BAD_REQUEST, format!("Invalid attr filter '{}', expected key:value", v))),
+    }).collect::<Result<Vec<_>, _>>().map(|pairs| pairs.into_iter().unzip())
+}
+
+/// Moves an image onto the CDN host, keeping its path; relative URLs get the base prepended. `params` (e.g. `w=800`) is appended to the query.
+fn cdn_image_url(url: &str, base: &str, params: Option<&str>) -> String {
+    let path = match url.split_once("://") { Some((_, rest)) => rest.find('/').map_or("", |i| &rest[i..]), None => url };
+    let mut rewritten = format!("{}/{}", base.trim_end_matches('/'), path.trim_start_matches('/'));
+    if let Some(params) = params { rewritten.push(if rewritten.contains('?') { '&' } else { '?' }); rewritten.push_str(params); }
+    rewritten
+}
+
+fn with_cdn_images(mut p: Product, base: Option<&str>, params: Option<&str>) -> Product {
+    if let Some(base) = base { p.images = p.images.iter().map(|url| cdn_image_url(url, base, params)).collect(); }
+    p
+}
+
+/// `StockStatus` from the row's stock, `inventory_policy` and `preorder_until`; products have no low-stock threshold
+/// column, so `low_stock` is never reported here.
+fn product_stock_status(p: &Product, now: DateTime<Utc>) -> StockStatus {
+    let policy = if p.inventory_policy == "continue" { InventoryPolicy::Continue } else { InventoryPolicy::Deny };
+    StockStatus::classify(p.inventory_quantity.max(0) as u32, policy, None, p.preorder_until, now)
+}
+
+impl AppState {
+    fn present(&self, mut p: Product) -> Product {
+        p.stock_status = Some(product_stock_status(&p, Utc::now()));
+        with_cdn_images(p, self.image_cdn_base.as_deref(), self.image_cdn_params.as_deref())
+    }
+}
+
+async fn list_products(State(s): State<AppState>, Query(p): Query<ListParams>, Query(raw): Query<Vec<(String, String)>>) -> Result<Json<PaginatedResponse<Product>>, (StatusCode, String)> {
+    let page = p.page.unwrap_or(1).max(1); let per_page = p.per_page.unwrap_or(20).clamp(1, 100);
+    let (attr_keys, attr_values) = attribute_filters(&raw)?;
+    let search = p.search.as_deref().and_then(search_term);
+    let from = "FROM products p LEFT JOIN product_categories pc ON pc.product_id = p.id AND pc.category_id = $1 WHERE p.status = 'active' AND ($1::uuid IS NULL OR p.category_id = $1 OR pc.category_id IS NOT NULL) AND (NOT $2 OR p.inventory_quantity > 0 OR p.inventory_policy = 'continue' OR p.preorder_until > NOW()) AND NOT EXISTS (SELECT 1 FROM unnest($3::text[], $4::text[]) a(k, v) WHERE p.metadata->>a.k IS DISTINCT FROM a.v) AND ($5::text IS NULL OR p.name ILIKE '%' || $5 || '%' OR p.description ILIKE '%' || $5 || '%')";
+    // Name matches rank above description-only matches; without a search term the CASE is constant.
+    let products = sqlx::query_as::<_, Product>(&format!("SELECT p.* {} ORDER BY CASE WHEN p.name ILIKE '%' || $5 || '%' THEN 0 ELSE 1 END, {} LIMIT $6 OFFSET $7", from, product_sort_clause(p.sort.as_deref(), p.category.is_some())))
+        .bind(p.category).bind(s.hide_out_of_stock).bind(&attr_keys).bind(&attr_values).bind(&search).bind(per_page as i64).bind(((page-1)*per_page) as i64).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let total: (i64,) = sqlx::query_as(&format!("SELECT COUNT(*) {}", from)).bind(p.category).bind(s.hide_out_of_stock).bind(&attr_keys).bind(&attr_values).bind(&search).fetch_one(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(PaginatedResponse::new(products.into_iter().map(|p| s.present(p)).collect(), total.0, page, per_page)))
 }
 
 async fn get_product(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<Product>, (StatusCode, String)> {
-    sqlx::query_as::<_, Product>("SELECT * FROM products WHERE id = $1").bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.map(Json).ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))
+    sqlx::query_as::<_, Product>("SELECT * FROM products WHERE id = $1").bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.map(|p| Json(s.present(p))).ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))
 }
 
-#[derive(Debug, Deserialize)] pub struct CreateProductRequest { pub name: String, pub description: Option<String>, pub price: i64, pub category_id: Option<Uuid>, pub inventory_quantity: Option<i32> }
+#[derive(Debug, Deserialize)] pub struct CreateProductRequest { pub name: String, pub description: Option<String>, pub price: i64, pub cost: Option<i64>, pub category_id: Option<Uuid>, pub inventory_quantity: Option<i32> }
 
 async fn create_product(State(s): State<AppState>, Json(r): Json<CreateProductRequest>) -> Result<(StatusCode, Json<Product>), (StatusCode, String)> {
     let sku = format!("SKU-{:08}", rand::random::<u32>());
-    let p = sqlx::query_as::<_, Product>("INSERT INTO products (id, sku, name, description, price, currency, category_id, inventory_quantity, status, images, tags, metadata, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, 'NGN', $6, $7, 'active', '{}', '{}', '{}', NOW(), NOW()) RETURNING *")
-        .bind(Uuid::now_v7()).bind(&sku).bind(&r.name).bind(&r.description).bind(r.price).bind(r.category_id).bind(r.inventory_quantity.unwrap_or(0))
+    let p = sqlx::query_as::<_, Product>("INSERT INTO products (id, sku, name, description, price, cost, currency, category_id, inventory_quantity, status, images, tags, metadata, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, $8, 'NGN', $6, $7, 'active', '{}', '{}', '{}', NOW(), NOW()) RETURNING *")
+        .bind(Uuid::now_v7()).bind(&sku).bind(&r.name).bind(&r.description).bind(r.price).bind(r.category_id).bind(r.inventory_quantity.unwrap_or(0)).bind(r.cost)
         .fetch_one(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok((StatusCode::CREATED, Json(p)))
+    Ok((StatusCode::CREATED, Json(s.present(p))))
+}
+
+#[derive(Debug, sqlx::FromRow)] struct UpdatedProduct { #[sqlx(flatten)] product: Product, previous_category_id: Option<Uuid> }
+
+/// Payload for `ecommerce.product.category_changed`; `None` when the category did not change.
+fn category_change_event(product_id: Uuid, from: Option<Uuid>, to: Option<Uuid>) -> Option<DomainEvent> {
+    (from != to).then(|| DomainEvent::Product(ProductEvent::CategoryChanged { product_id: product_id.to_string(), from: from.map(|c| c.to_string()), to: to.map(|c| c.to_string()) }))
 }
 
 async fn update_product(State(s): State<AppState>, Path(id): Path<Uuid>, Json(r): Json<CreateProductRequest>) -> Result<Json<Product>, (StatusCode, String)> {
-    let p = sqlx::query_as::<_, Product>("UPDATE products SET name = $2, description = $3, price = $4, category_id = $5, inventory_quantity = $6, updated_at = NOW() WHERE id = $1 RETURNING *")
-        .bind(id).bind(&r.name).bind(&r.description).bind(r.price).bind(r.category_id).bind(r.inventory_quantity.unwrap_or(0))
+    let updated = sqlx::query_as::<_, UpdatedProduct>("WITH old AS (SELECT category_id FROM products WHERE id = $1) UPDATE products SET name = $2, description = $3, price = $4, compare_at_price = CASE WHEN NOT $8 THEN compare_at_price WHEN $4 < price THEN price WHEN compare_at_price <= $4 THEN NULL ELSE compare_at_price END, category_id = $5, inventory_quantity = $6, cost = $7, updated_at = NOW() WHERE id = $1 RETURNING products.*, (SELECT category_id FROM old) AS previous_category_id")
+        .bind(id).bind(&r.name).bind(&r.description).bind(r.price).bind(r.category_id).bind(r.inventory_quantity.unwrap_or(0)).bind(r.cost).bind(s.auto_compare_at_price)
         .fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))?;
-    Ok(Json(p))
+    if let Some(event) = category_change_event(id, updated.previous_category_id, updated.product.category_id) {
+        publish_event(&s.nats, "ecommerce.product.category_changed", &event).await;
+    }
+    Ok(Json(s.present(updated.product)))
 }
 
 async fn delete_product(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<StatusCode, (StatusCode, String)> {
@@ -103,67 +307,1301 @@ async fn delete_product(State(s): State<AppState>, Path(id): Path<Uuid>) -> Resu
     Ok(StatusCode::NO_CONTENT)
 }
 
+/// Undoes a soft delete into `draft` so the product has to be deliberately republished.
+async fn restore_product(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<Product>, (StatusCode, String)> {
+    let restored = sqlx::query_as::<_, Product>("UPDATE products SET status = 'draft', updated_at = NOW() WHERE id = $1 AND status = 'deleted' RETURNING *").bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let Some(p) = restored { return Ok(Json(s.present(p))); }
+    let exists: Option<(Uuid,)> = sqlx::query_as("SELECT id FROM products WHERE id = $1").bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Err(match exists { Some(_) => (StatusCode::CONFLICT, "Product is not deleted".to_string()), None => (StatusCode::NOT_FOUND, "Not found".to_string()) })
+}
+
+#[derive(Debug, Deserialize)] pub struct StockTakeRequest { pub counted_quantity: i32 }
+#[derive(Debug, Serialize)] pub struct StockTakeResponse { pub product_id: Uuid, pub previous_quantity: i32, pub counted_quantity: i32, pub variance: i32 }
+
+fn stock_variance(system: i32, counted: i32) -> i32 { counted - system }
+
+/// Records the counted-vs-system variance as a `stock_take` adjustment and sets inventory to the counted value.
+async fn stock_take(State(s): State<AppState>, Path(id): Path<Uuid>, Json(r): Json<StockTakeRequest>) -> Result<Json<StockTakeResponse>, (StatusCode, String)> {
+    if r.counted_quantity < 0 { return Err((StatusCode::BAD_REQUEST, "counted_quantity must not be negative".to_string())); }
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (previous,): (i32,) = sqlx::query_as("SELECT inventory_quantity FROM products WHERE id = $1 FOR UPDATE").bind(id).fetch_optional(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))?;
+    let variance = stock_variance(previous, r.counted_quantity);
+    sqlx::query("INSERT INTO inventory_adjustments (id, product_id, previous_quantity, new_quantity, variance, reason, created_at) VALUES ($1, $2, $3, $4, $5, 'stock_take', NOW())")
+        .bind(Uuid::now_v7()).bind(id).bind(previous).bind(r.counted_quantity).bind(variance)
+        .execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    sqlx::query("UPDATE products SET inventory_quantity = $2, updated_at = NOW() WHERE id = $1").bind(id).bind(r.counted_quantity).execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(StockTakeResponse { product_id: id, previous_quantity: previous, counted_quantity: r.counted_quantity, variance }))
+}
+
+#[derive(Debug, PartialEq, Eq, Serialize)] pub struct StockBreakdown { pub on_hand: i64, pub committed: i64, pub reserved: i64, pub available: i64 }
+
+/// Orders decrement `inventory_quantity` when placed, so `committed` is already out of `on_hand` and is shown for
+/// visibility only; quantities in live carts are held against what is left.
+fn stock_breakdown(on_hand: i64, committed: i64, reserved: i64) -> StockBreakdown {
+    StockBreakdown { on_hand, committed, reserved, available: (on_hand - reserved).max(0) }
+}
+
+/// A cart only reserves stock while it is live: like `Cart::with_ttl`, it lapses `cart_ttl` after its last item change.
+async fn get_stock_breakdown(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<StockBreakdown>, (StatusCode, String)> {
+    let (on_hand, committed, reserved): (i64, i64, i64) = sqlx::query_as("SELECT p.inventory_quantity::bigint, (SELECT COALESCE(SUM(oi.quantity), 0)::bigint FROM order_items oi JOIN orders o ON o.id = oi.order_id WHERE oi.product_id = p.id AND o.status IN ('confirmed', 'processing') AND o.fulfillment_status <> 'fulfilled'), (SELECT COALESCE(SUM(c.quantity), 0)::bigint FROM cart_items c WHERE c.product_id = p.id AND c.session_id IN (SELECT session_id FROM cart_items GROUP BY session_id HAVING MAX(updated_at) > $2)) FROM products p WHERE p.id = $1")
+        .bind(id).bind(Utc::now() - s.cart_ttl).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))?;
+    Ok(Json(stock_breakdown(on_hand, committed, reserved)))
+}
+
+#[derive(Debug, Serialize)] pub struct MarginReportRow { pub product_id: Uuid, pub sku: String, pub name: String, pub price: i64, pub cost: i64, pub margin: i64, pub margin_percentage: Option<rust_decimal::Decimal>, pub negative_margin: bool }
+
+fn margin_row(p: &Product, cost: i64) -> MarginReportRow {
+    let margin = p.price - cost;
+    let margin_percentage = (p.price != 0).then(|| (rust_decimal::Decimal::from(margin) * rust_decimal::Decimal::from(100) / rust_decimal::Decimal::from(p.price)).round_dp(2));
+    MarginReportRow { product_id: p.id, sku: p.sku.clone(), name: p.name.clone(), price: p.price, cost, margin, margin_percentage, negative_margin: margin < 0 }
+}
+
+/// Products with a recorded cost, highest margin first; loss-making products are flagged rather than hidden.
+async fn margin_report(State(s): State<AppState>) -> Result<Json<Vec<MarginReportRow>>, (StatusCode, String)> {
+    let products = sqlx::query_as::<_, Product>("SELECT * FROM products WHERE cost IS NOT NULL AND status <> 'deleted' ORDER BY price - cost DESC").fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(products.iter().filter_map(|p| p.cost.map(|c| margin_row(p, c))).collect()))
+}
+
 async fn list_categories(State(s): State<AppState>) -> Result<Json<Vec<Category>>, (StatusCode, String)> {
-    let cats = sqlx::query_as::<_, Category>("SELECT * FROM categories ORDER BY name").fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let cats = sqlx::query_as::<_, Category>(&format!("{} ORDER BY c.name", CATEGORY_WITH_COUNT)).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok(Json(cats))
 }
 
 async fn get_category(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<Category>, (StatusCode, String)> {
-    sqlx::query_as::<_, Category>("SELECT * FROM categories WHERE id = $1").bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.map(Json).ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))
+    sqlx::query_as::<_, Category>(&format!("{} WHERE c.id = $1", CATEGORY_WITH_COUNT)).bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.map(Json).ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))
+}
+
+const BREADCRUMB_MAX_DEPTH: usize = 20;
+
+/// Orders `chain` root-first by following `parent_id` up from `leaf`; stops at a missing parent, a revisited node, or
+/// `BREADCRUMB_MAX_DEPTH` levels.
+fn breadcrumb(leaf: Uuid, chain: Vec<Category>) -> Vec<Category> {
+    let mut by_id: HashMap<Uuid, Category> = chain.into_iter().map(|c| (c.id, c)).collect();
+    let mut path = Vec::new();
+    let mut next = Some(leaf);
+    while let Some(c) = next.and_then(|id| by_id.remove(&id)) {
+        next = c.parent_id;
+        path.push(c);
+        if path.len() == BREADCRUMB_MAX_DEPTH { break; }
+    }
+    path.reverse();
+    path
+}
+
+async fn category_breadcrumb(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<Vec<Category>>, (StatusCode, String)> {
+    let chain = sqlx::query_as::<_, Category>(&format!("WITH RECURSIVE chain AS (SELECT id, parent_id, 1 AS depth FROM categories WHERE id = $1 UNION ALL SELECT c.id, c.parent_id, chain.depth + 1 FROM categories c JOIN chain ON c.id = chain.parent_id WHERE chain.depth < $2) {} WHERE c.id IN (SELECT id FROM chain)", CATEGORY_WITH_COUNT))
+        .bind(id).bind(BREADCRUMB_MAX_DEPTH as i32).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if chain.is_empty() { return Err((StatusCode::NOT_FOUND, "Not found".to_string())); }
+    Ok(Json(breadcrumb(id, chain)))
 }
 
 #[derive(Debug, Deserialize)] pub struct CreateCategoryRequest { pub name: String, pub description: Option<String>, pub parent_id: Option<Uuid> }
 
 async fn create_category(State(s): State<AppState>, Json(r): Json<CreateCategoryRequest>) -> Result<(StatusCode, Json<Category>), (StatusCode, String)> {
-    let slug = r.name.to_lowercase().replace(' ', "-");
+    let slug = slugify(&r.name);
     let c = sqlx::query_as::<_, Category>("INSERT INTO categories (id, name, slug, description, parent_id, created_at) VALUES ($1, $2, $3, $4, $5, NOW()) RETURNING *")
         .bind(Uuid::now_v7()).bind(&r.name).bind(&slug).bind(&r.description).bind(r.parent_id)
         .fetch_one(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok((StatusCode::CREATED, Json(c)))
 }
 
-async fn list_orders(State(s): State<AppState>, Query(p): Query<ListParams>) -> Result<Json<PaginatedResponse<Order>>, (StatusCode, String)> {
-    let page = p.page.unwrap_or(1).max(1); let per_page = p.per_page.unwrap_or(20).min(100);
-    let orders = sqlx::query_as::<_, Order>("SELECT * FROM orders ORDER BY created_at DESC LIMIT $1 OFFSET $2")
+/// `ancestors` is the chain from `proposed_parent` up to the root (inclusive); reparenting under any of them that is
+/// `category_id` itself would loop the tree.
+fn creates_cycle(category_id: Uuid, proposed_parent: Uuid, ancestors: &[Uuid]) -> bool {
+    proposed_parent == category_id || ancestors.contains(&category_id)
+}
+
+/// Walks up from `proposed_parent`; `UNION` stops the recursion even if the stored tree already contains a loop.
+async fn would_create_cycle(db: &sqlx::PgPool, category_id: Uuid, proposed_parent: Uuid) -> Result<bool, sqlx::Error> {
+    let ancestors: Vec<(Uuid,)> = sqlx::query_as("WITH RECURSIVE chain AS (SELECT id, parent_id FROM categories WHERE id = $1 UNION SELECT c.id, c.parent_id FROM categories c JOIN chain ON c.id = chain.parent_id) SELECT id FROM chain")
+        .bind(proposed_parent).fetch_all(db).await?;
+    Ok(creates_cycle(category_id, proposed_parent, &ancestors.into_iter().map(|a| a.0).collect::<Vec<_>>()))
+}
+
+/// Renames regenerate the slug; the response carries the live product count like `get_category`.
+async fn update_category(State(s): State<AppState>, Path(id): Path<Uuid>, Json(r): Json<CreateCategoryRequest>) -> Result<Json<Category>, (StatusCode, String)> {
+    if let Some(parent) = r.parent_id {
+        if would_create_cycle(&s.db, id, parent).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))? {
+            return Err((StatusCode::BAD_REQUEST, "parent_id cannot be the category itself or one of its descendants".to_string()));
+        }
+    }
+    sqlx::query_as::<_, Category>("WITH c AS (UPDATE categories SET name = $2, slug = $3, description = $4, parent_id = $5 WHERE id = $1 RETURNING *) SELECT c.*, (SELECT COUNT(*) FROM products p WHERE p.category_id = c.id AND p.status <> 'deleted') AS product_count FROM c")
+        .bind(id).bind(&r.name).bind(slugify(&r.name)).bind(&r.description).bind(r.parent_id)
+        .fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.map(Json).ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))
+}
+
+fn category_delete_blocker(products: i64, children: i64) -> Option<String> {
+    match (products, children) {
+        (0, 0) => None,
+        (p, 0) => Some(format!("Category still has {} product(s)", p)),
+        (_, c) => Some(format!("Category still has {} child categor{}", c, if c == 1 { "y" } else { "ies" })),
+    }
+}
+
+/// Refuses with 409 while products (primary or secondary membership, including soft-deleted ones) or child categories remain.
+async fn delete_category(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<StatusCode, (StatusCode, String)> {
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let exists: Option<(Uuid,)> = sqlx::query_as("SELECT id FROM categories WHERE id = $1 FOR UPDATE").bind(id).fetch_optional(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if exists.is_none() { return Err((StatusCode::NOT_FOUND, "Not found".to_string())); }
+    let (products, children): (i64, i64) = sqlx::query_as("SELECT (SELECT COUNT(*) FROM products WHERE category_id = $1) + (SELECT COUNT(*) FROM product_categories WHERE category_id = $1), (SELECT COUNT(*) FROM categories WHERE parent_id = $1)")
+        .bind(id).fetch_one(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if let Some(reason) = category_delete_blocker(products, children) { return Err((StatusCode::CONFLICT, reason)); }
+    sqlx::query("DELETE FROM categories WHERE id = $1").bind(id).execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+#[derive(Debug, Deserialize)] pub struct BulkTagRequest { pub order_ids: Vec<Uuid>, #[serde(default)] pub add_tags: Vec<String>, #[serde(default)] pub remove_tags: Vec<String> }
+#[derive(Debug, Serialize, sqlx::FromRow)] pub struct OrderTags { pub id: Uuid, pub tags: Vec<String> }
+#[derive(Debug, Serialize)] pub struct BulkTagResponse { pub updated: Vec<OrderTags>, pub unknown_order_ids: Vec<Uuid> }
+
+/// Trims, drops blanks and deduplicates submitted tags.
+fn normalize_tags(tags: &[String]) -> Vec<String> {
+    let mut tags: Vec<String> = tags.iter().map(|t| t.trim().to_string()).filter(|t| !t.is_empty()).collect();
+    tags.sort(); tags.dedup();
+    tags
+}
+
+fn unknown_ids(requested: &[Uuid], found: &[OrderTags]) -> Vec<Uuid> {
+    let mut unknown: Vec<Uuid> = requested.iter().filter(|id| !found.iter().any(|o| o.id == **id)).copied().collect();
+    unknown.sort(); unknown.dedup();
+    unknown
+}
+
+/// Applies tag changes to every listed order in a single statement; each order's tags stay sorted and unique.
+async fn bulk_tag_orders(State(s): State<AppState>, Json(r): Json<BulkTagRequest>) -> Result<Json<BulkTagResponse>, (StatusCode, String)> {
+    let (add, remove) = (normalize_tags(&r.add_tags), normalize_tags(&r.remove_tags));
+    let updated = sqlx::query_as::<_, OrderTags>("UPDATE orders SET tags = ARRAY(SELECT DISTINCT t FROM unnest(tags || $2::text[]) t WHERE t <> ALL($3::text[]) ORDER BY t), updated_at = NOW() WHERE id = ANY($1) RETURNING id, tags")
+        .bind(&r.order_ids).bind(&add).bind(&remove).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let unknown_order_ids = unknown_ids(&r.order_ids, &updated);
+    Ok(Json(BulkTagResponse { updated, unknown_order_ids }))
+}
+
+#[derive(Debug, Deserialize)] pub struct ReorderProductsRequest { pub product_ids: Vec<Uuid> }
+
+/// Sets the manual sort order within a category to the submitted sequence (0, 1, 2, ...).
+async fn reorder_category_products(State(s): State<AppState>, Path(id): Path<Uuid>, Json(r): Json<ReorderProductsRequest>) -> Result<StatusCode, (StatusCode, String)> {
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    for (position, product_id) in r.product_ids.iter().enumerate() {
+        sqlx::query("INSERT INTO product_categories (product_id, category_id, sort_order) VALUES ($1, $2, $3) ON CONFLICT (product_id, category_id) DO UPDATE SET sort_order = $3")
+            .bind(product_id).bind(id).bind(position as i32)
+            .execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
+}
+
+async fn list_orders(State(s): State<AppState>, Query(p): Query<ListParams>) -> Result<Json<PaginatedResponse<OrderResponse>>, (StatusCode, String)> {
+    let page = p.page.unwrap_or(1).max(1); let per_page = p.per_page.unwrap_or(20).clamp(1, 100);
+    let orders = sqlx::query_as::<_, Order>(&format!("SELECT * FROM orders ORDER BY {} LIMIT $1 OFFSET $2", order_sort_clause(p.sort.as_deref())))
         .bind(per_page as i64).bind(((page-1)*per_page) as i64).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     let total: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM orders").fetch_one(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok(Json(PaginatedResponse { data: orders, total: total.0, page }))
+    Ok(Json(PaginatedResponse::new(orders.into_iter().map(OrderResponse::from).collect(), total.0, page, per_page)))
 }
 
-async fn get_order(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<Order>, (StatusCode, String)> {
-    sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1").bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.map(Json).ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))
+async fn get_order(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<OrderResponse>, (StatusCode, String)> {
+    sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1").bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.map(|o| Json(o.into())).ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))
+}
+
+#[derive(Debug, Clone, sqlx::FromRow)] struct ExportItemRow { order_number: String, currency: String, sku: String, name: String, quantity: i32, unit_price: i64, total: i64 }
+
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) { format!("\"{}\"", value.replace('"', "\"\"")) } else { value.to_string() }
+}
+
+/// One row per line item; amounts are plain decimals in the order currency so spreadsheets can sum them.
+fn order_items_csv(rows: &[ExportItemRow]) -> String {
+    let mut out = String::from("order_number,sku,name,quantity,unit_price,line_total,currency\n");
+    for r in rows {
+        let amount = |minor: i64| rust_decimal::Decimal::new(minor, minor_units(&r.currency)).to_string();
+        out.push_str(&format!("{},{},{},{},{},{},{}\n", csv_field(&r.order_number), csv_field(&r.sku), csv_field(&r.name), r.quantity, amount(r.unit_price), amount(r.total), r.currency));
+    }
+    out
+}
+
+/// Rows are grouped by order number, newest order first, so each order's lines sit together.
+async fn export_order_items(State(s): State<AppState>) -> Result<([(header::HeaderName, &'static str); 2], String), (StatusCode, String)> {
+    let rows = sqlx::query_as::<_, ExportItemRow>("SELECT o.order_number, o.currency, i.sku, i.name, i.quantity, i.unit_price, i.total FROM order_items i JOIN orders o ON o.id = i.order_id ORDER BY o.created_at DESC, o.order_number, i.id")
+        .fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(([(header::CONTENT_TYPE, "text/csv; charset=utf-8"), (header::CONTENT_DISPOSITION, "attachment; filename=\"order-items.csv\"")], order_items_csv(&rows)))
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)] pub struct TotalDiscrepancy { pub field: String, pub stored: i64, pub computed: i64 }
+#[derive(Debug, Serialize)] pub struct VerifyOrderResponse { pub order_id: Uuid, pub consistent: bool, pub discrepancies: Vec<TotalDiscrepancy> }
+
+/// Recomputes line, subtotal, and total amounts from the order items and reports any stored value that disagrees.
+fn verify_totals(order: &Order, items: &[OrderItem]) -> Result<(), Vec<TotalDiscrepancy>> {
+    let mut discrepancies = Vec::new();
+    let mut check = |field: String, stored: i64, computed: i64| if stored != computed { discrepancies.push(TotalDiscrepancy { field, stored, computed }) };
+    for item in items { check(format!("items.{}.total", item.id), item.total, item.unit_price * item.quantity as i64); }
+    let subtotal: i64 = items.iter().map(|i| i.unit_price * i.quantity as i64).sum();
+    check("subtotal".to_string(), order.subtotal, subtotal);
+    check("total".to_string(), order.total, subtotal + order.tax + order.shipping);
+    if discrepancies.is_empty() { Ok(()) } else { Err(discrepancies) }
+}
+
+async fn verify_order(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<VerifyOrderResponse>, (StatusCode, String)> {
+    let order = sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE id = $1").bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))?;
+    let items = sqlx::query_as::<_, OrderItem>("SELECT * FROM order_items WHERE order_id = $1").bind(id).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let discrepancies = verify_totals(&order, &items).err().unwrap_or_default();
+    Ok(Json(VerifyOrderResponse { order_id: id, consistent: discrepancies.is_empty(), discrepancies }))
+}
+
+const REQUIRED_ADDRESS_FIELDS: [&str; 5] = ["name", "street1", "city", "zip", "country"];
+
+fn validate_address(address: &serde_json::Value) -> Result<(), String> {
+    let missing: Vec<_> = REQUIRED_ADDRESS_FIELDS.iter().filter(|f| !address.get(**f).and_then(|v| v.as_str()).is_some_and(|v| !v.trim().is_empty())).copied().collect();
+    if missing.is_empty() { Ok(()) } else { Err(format!("Missing address fields: {}", missing.join(", "))) }
+}
+
+/// Only unfulfilled orders can change destination; tax and total are recomputed for the new destination. The row is
+/// locked and the update stays conditional, so a concurrent shipment wins.
+async fn update_shipping_address(State(s): State<AppState>, Path(id): Path<Uuid>, Json(address): Json<serde_json::Value>) -> Result<Json<OrderResponse>, (StatusCode, String)> {
+    validate_address(&address).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let current: Option<(i64, String)> = sqlx::query_as("SELECT subtotal, currency FROM orders WHERE id = $1 FOR UPDATE").bind(id).fetch_optional(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (subtotal, currency) = current.ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))?;
+    let tax = destination_tax(&s.tax, subtotal, &currency, &address);
+    let updated = sqlx::query_as::<_, Order>("UPDATE orders SET shipping_address = $2, tax = $3, total = subtotal + shipping + $3, updated_at = NOW() WHERE id = $1 AND fulfillment_status = 'unfulfilled' AND status NOT IN ('shipped', 'delivered', 'cancelled') RETURNING *")
+        .bind(id).bind(&address).bind(tax).fetch_optional(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let o = updated.ok_or((StatusCode::CONFLICT, "Order already fulfilled".to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(o.into()))
+}
+
+/// Records the payment for a pending order that is still live and announces it on `ecommerce.order.paid`.
+async fn mark_order_paid(State(s): State<AppState>, Path(id): Path<Uuid>) -> Result<Json<OrderResponse>, (StatusCode, String)> {
+    let paid = sqlx::query_as::<_, Order>("UPDATE orders SET payment_status = 'paid', updated_at = NOW() WHERE id = $1 AND payment_status = 'pending' AND status NOT IN ('cancelled', 'refunded') RETURNING *")
+        .bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let Some(o) = paid else {
+        let exists: Option<(Uuid,)> = sqlx::query_as("SELECT id FROM orders WHERE id = $1").bind(id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        return Err(match exists { None => (StatusCode::NOT_FOUND, "Not found".to_string()), Some(_) => (StatusCode::CONFLICT, "Order is not awaiting payment".to_string()) });
+    };
+    publish_order_event(&s.nats, &OrderEvent::Paid { order_id: o.id.to_string() }).await;
+    Ok(Json(o.into()))
 }
 
 #[derive(Debug, Deserialize)] pub struct CreateOrderRequest { pub customer_email: String, pub items: Vec<OrderItemRequest>, pub shipping_address: serde_json::Value }
 #[derive(Debug, Deserialize)] pub struct OrderItemRequest { pub product_id: Uuid, pub quantity: i32 }
 
-async fn create_order(State(s): State<AppState>, Json(r): Json<CreateOrderRequest>) -> Result<(StatusCode, Json<Order>), (StatusCode, String)> {
-    let order_num = format!("ORD-{:08}", rand::random::<u32>());
-    let o = sqlx::query_as::<_, Order>("INSERT INTO orders (id, order_number, customer_email, status, subtotal, tax, shipping, total, currency, shipping_address, billing_address, payment_status, fulfillment_status, created_at, updated_at) VALUES ($1, $2, $3, 'pending', 0, 0, 0, 0, 'NGN', $4, '{}', 'pending', 'unfulfilled', NOW(), NOW()) RETURNING *")
-        .bind(Uuid::now_v7()).bind(&order_num).bind(&r.customer_email).bind(&r.shipping_address)
-        .fetch_one(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
-    Ok((StatusCode::CREATED, Json(o)))
+/// Why a line could not be priced and reserved, given the product's `(sku, status)` if the row exists.
+fn unorderable_line(product_id: Uuid, product: Option<(String, String)>) -> (StatusCode, String) {
+    match product {
+        None => (StatusCode::UNPROCESSABLE_ENTITY, format!("Unknown product {}", product_id)),
+        Some((sku, status)) if status != "active" => (StatusCode::UNPROCESSABLE_ENTITY, format!("Product {} is not available for sale", sku)),
+        Some((sku, _)) => (StatusCode::CONFLICT, format!("Insufficient inventory for SKU {}", sku)),
+    }
+}
+
+/// Reads the optional `Idempotency-Key` header; blank, non-ASCII, or over-long keys are rejected rather than ignored.
+fn idempotency_key(headers: &HeaderMap) -> Result<Option<String>, (StatusCode, String)> {
+    let Some(value) = headers.get("idempotency-key") else { return Ok(None) };
+    match value.to_str().map(str::trim) {
+        Ok(key) if !key.is_empty() && key.len() <= 255 => Ok(Some(key.to_string())),
+        _ => Err((StatusCode::BAD_REQUEST, "Idempotency-Key must be 1-255 visible ASCII characters".to_string())),
+    }
+}
+
+async fn order_for_idempotency_key(db: &sqlx::PgPool, email: &str, key: &str) -> Result<Option<Order>, (StatusCode, String)> {
+    sqlx::query_as::<_, Order>("SELECT o.* FROM idempotency_keys k JOIN orders o ON o.id = k.order_id WHERE k.customer_email = $1 AND k.key = $2")
+        .bind(email).bind(key).fetch_optional(db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))
+}
+
+/// Stock is decremented with a guarded UPDATE per line so concurrent orders can never oversell; the first
+/// line that cannot be covered aborts the whole order with `409`; unknown or inactive products are rejected with `422`.
+/// With an `Idempotency-Key`, a retry from the same customer email replays the original order instead of placing another.
+async fn create_order(State(s): State<AppState>, headers: HeaderMap, Json(r): Json<CreateOrderRequest>) -> Result<(StatusCode, Json<OrderResponse>), (StatusCode, String)> {
+    s.checkout_fields.validate(&r.customer_email, &r.shipping_address)?;
+    let key = idempotency_key(&headers)?;
+    if let Some(key) = &key {
+        if let Some(o) = order_for_idempotency_key(&s.db, &r.customer_email, key).await? { return Ok((StatusCode::CREATED, Json(o.into()))); }
+    }
+    if r.items.is_empty() || r.items.iter().any(|i| i.quantity <= 0) { return Err((StatusCode::UNPROCESSABLE_ENTITY, "Order needs at least one item with a positive quantity".to_string())); }
+    if !s.order_limiter.allows(&r.customer_email, Utc::now()) { return Err((StatusCode::TOO_MANY_REQUESTS, "Too many orders for this email, try again later".to_string())); }
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut lines = Vec::with_capacity(r.items.len());
+    for item in &r.items {
+        let line = sqlx::query_as::<_, CheckoutLine>("UPDATE products SET inventory_quantity = inventory_quantity - $2, updated_at = NOW() WHERE id = $1 AND status = 'active' AND inventory_quantity >= $2 RETURNING id AS product_id, sku, name, price, currency, inventory_quantity, $2 AS quantity")
+            .bind(item.product_id).bind(item.quantity).fetch_optional(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        match line {
+            Some(l) => lines.push(l),
+            None => {
+                let product: Option<(String, String)> = sqlx::query_as("SELECT sku, status FROM products WHERE id = $1").bind(item.product_id).fetch_optional(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+                return Err(unorderable_line(item.product_id, product));
+            }
+        }
+    }
+    let o = insert_order(&mut tx, &s.tax, &r.customer_email, &r.shipping_address, &lines).await?;
+    if let Some(key) = &key {
+        // A concurrent retry may have claimed the key first; drop this attempt (restoring stock) and replay the winner.
+        let claimed = sqlx::query("INSERT INTO idempotency_keys (customer_email, key, order_id) VALUES ($1, $2, $3) ON CONFLICT DO NOTHING")
+            .bind(&r.customer_email).bind(key).bind(o.id).execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.rows_affected() == 1;
+        if !claimed {
+            tx.rollback().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+            let original = order_for_idempotency_key(&s.db, &r.customer_email, key).await?.ok_or((StatusCode::CONFLICT, "Order for this Idempotency-Key is still being created".to_string()))?;
+            return Ok((StatusCode::CREATED, Json(original.into())));
+        }
+    }
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    s.order_limiter.record(&r.customer_email, Utc::now());
+    publish_order_event(&s.nats, &order_created_event(&o)).await;
+    Ok((StatusCode::CREATED, Json(o.into())))
+}
+
+/// Transport behind `publish_event`, so tests can capture what would go over NATS.
+trait EventPublisher { async fn publish_bytes(&self, subject: String, payload: Vec<u8>) -> Result<(), String>; }
+
+impl EventPublisher for async_nats::Client {
+    async fn publish_bytes(&self, subject: String, payload: Vec<u8>) -> Result<(), String> { self.publish(subject, payload.into()).await.map_err(|e| e.to_string()) }
+}
+
+/// Fire-and-forget: skipped when NATS is not configured, and a failed publish is logged rather than failing the request.
+async fn publish_event<P: EventPublisher>(nats: &Option<P>, subject: &str, payload: &impl Serialize) {
+    let Some(nats) = nats else { return };
+    let result = match serde_json::to_vec(payload) { Ok(bytes) => nats.publish_bytes(subject.to_string(), bytes).await, Err(e) => Err(e.to_string()) };
+    if let Err(e) = result { tracing::warn!(error = %e, subject, "failed to publish event"); }
+}
+
+/// NATS subject each order lifecycle event is published on.
+fn order_event_subject(event: &OrderEvent) -> &'static str {
+    match event {
+        OrderEvent::Created { .. } => "ecommerce.order.created",
+        OrderEvent::Confirmed { .. } => "ecommerce.order.confirmed",
+        OrderEvent::Paid { .. } => "ecommerce.order.paid",
+        OrderEvent::PartiallyFulfilled { .. } => "ecommerce.order.partially_fulfilled",
+        OrderEvent::Shipped { .. } => "ecommerce.order.shipped",
+        OrderEvent::Delivered { .. } => "ecommerce.order.delivered",
+        OrderEvent::Cancelled { .. } => "ecommerce.order.cancelled",
+        OrderEvent::Refunded { .. } => "ecommerce.order.refunded",
+    }
+}
+
+async fn publish_order_event<P: EventPublisher>(nats: &Option<P>, event: &OrderEvent) { publish_event(nats, order_event_subject(event), event).await }
+
+/// Guest orders have no customer id, so their email stands in for it.
+fn order_created_event(o: &Order) -> OrderEvent {
+    OrderEvent::Created { order_id: o.id.to_string(), customer_id: o.customer_id.map_or_else(|| o.customer_email.clone(), |id| id.to_string()) }
+}
+
+/// Cancels pending, unpaid orders created before `now - timeout` and puts their items back in stock.
+/// Paid or confirmed orders are never touched. Returns the cancelled order ids.
+async fn cancel_stale_unpaid(pool: &sqlx::PgPool, timeout: chrono::Duration, now: DateTime<Utc>) -> Result<Vec<Uuid>, sqlx::Error> {
+    let mut tx = pool.begin().await?;
+    let cancelled: Vec<(Uuid,)> = sqlx::query_as("UPDATE orders SET status = 'cancelled', updated_at = $2 WHERE status = 'pending' AND payment_status = 'pending' AND created_at < $1 RETURNING id")
+        .bind(now - timeout).bind(now).fetch_all(&mut *tx).await?;
+    let ids: Vec<Uuid> = cancelled.into_iter().map(|(id,)| id).collect();
+    sqlx::query("UPDATE products p SET inventory_quantity = p.inventory_quantity + i.quantity, updated_at = $2 FROM (SELECT product_id, SUM(quantity)::int AS quantity FROM order_items WHERE order_id = ANY($1) GROUP BY product_id) i WHERE p.id = i.product_id")
+        .bind(&ids).bind(now).execute(&mut *tx).await?;
+    tx.commit().await?;
+    Ok(ids)
+}
+
+async fn prune_order_limiter_task(limiter: OrderRateLimiter) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(300));
+    loop {
+        interval.tick().await;
+        limiter.prune(Utc::now());
+    }
+}
+
+async fn cancel_stale_unpaid_task(state: AppState, timeout: chrono::Duration) {
+    let mut interval = tokio::time::interval(std::time::Duration::from_secs(300));
+    loop {
+        interval.tick().await;
+        match cancel_stale_unpaid(&state.db, timeout, Utc::now()).await {
+            Ok(ids) => for id in ids {
+                tracing::info!(order_id = %id, "cancelled unpaid order after timeout");
+                publish_order_event(&state.nats, &OrderEvent::Cancelled { order_id: id.to_string() }).await;
+            },
+            Err(e) => tracing::error!(error = %e, "unpaid order cancellation failed"),
+        }
+    }
+}
+
+fn luhn_check_digit(digits: &str) -> Option<u32> {
+    let sum = digits.chars().rev().enumerate().try_fold(0, |sum, (i, c)| {
+        let d = c.to_digit(10)?;
+        Some(sum + if i % 2 == 0 { if d * 2 > 9 { d * 2 - 9 } else { d * 2 } } else { d })
+    })?;
+    Some((10 - sum % 10) % 10)
+}
+
+/// `ORD-` followed by the zero-padded sequence and a trailing Luhn check digit.
+fn generate_order_number(sequence: u32) -> String {
+    let digits = format!("{:08}", sequence);
+    format!("ORD-{}{}", digits, luhn_check_digit(&digits).unwrap_or(0))
+}
+
+/// Catches mistyped order numbers (wrong digit, most adjacent swaps) before they reach the database.
+pub fn validate_order_number(s: &str) -> bool {
+    let digits = s.trim().strip_prefix("ORD-").unwrap_or(s.trim());
+    if !digits.bytes().all(|b| b.is_ascii_digit()) { return false; }
+    match digits.len().checked_sub(1).map(|n| digits.split_at(n)) {
+        Some((body, check)) if !body.is_empty() => luhn_check_digit(body).zip(check.parse().ok()).is_some_and(|(expected, given)| expected == given),
+        _ => false,
+    }
+}
+
+async fn get_order_by_number(State(s): State<AppState>, Path(number): Path<String>) -> Result<Json<OrderResponse>, (StatusCode, String)> {
+    if !validate_order_number(&number) { return Err((StatusCode::BAD_REQUEST, "Invalid order number".to_string())); }
+    let number = format!("ORD-{}", number.trim().trim_start_matches("ORD-"));
+    sqlx::query_as::<_, Order>("SELECT * FROM orders WHERE order_number = $1").bind(&number).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.map(|o| Json(o.into())).ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))
+}
+
+/// Writes a pending order and its items priced from `lines`, taxed for its destination; inventory must already be
+/// adjusted by the caller.
+async fn insert_order(conn: &mut sqlx::PgConnection, tax_rates: &RegionalTax, email: &str, shipping_address: &serde_json::Value, lines: &[CheckoutLine]) -> Result<Order, (StatusCode, String)> {
+    // No shipping rules are configured yet, so shipping is zero.
+    let currency = order_currency(lines)?;
+    let subtotal = checkout_subtotal(lines);
+    let (tax, shipping) = (destination_tax(tax_rates, subtotal, currency, shipping_address), 0_i64);
+    let order = sqlx::query_as::<_, Order>("INSERT INTO orders (id, order_number, customer_email, status, subtotal, tax, shipping, total, currency, shipping_address, billing_address, payment_status, fulfillment_status, created_at, updated_at) VALUES ($1, $2, $3, 'pending', $4, $5, $6, $7, $8, $9, '{}', 'pending', 'unfulfilled', NOW(), NOW()) RETURNING *")
+        .bind(Uuid::now_v7()).bind(generate_order_number(rand::random::<u32>())).bind(email).bind(subtotal).bind(tax).bind(shipping).bind(subtotal + tax + shipping).bind(currency).bind(shipping_address)
+        .fetch_one(&mut *conn).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    for l in lines {
+        sqlx::query("INSERT INTO order_items (id, order_id, product_id, sku, name, quantity, unit_price, total) VALUES ($1, $2, $3, $4, $5, $6, $7, $8)")
+            .bind(Uuid::now_v7()).bind(order.id).bind(l.product_id).bind(&l.sku).bind(&l.name).bind(l.quantity).bind(l.price).bind(l.price * l.quantity as i64)
+            .execute(&mut *conn).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    Ok(order)
+}
+
+#[derive(Debug, Deserialize)] pub struct FulfillmentUpdate { pub order_id: Uuid, pub status: String, pub tracking: Option<String>, pub carrier: Option<String> }
+#[derive(Debug, Serialize)] pub struct RejectedUpdate { pub order_id: Uuid, pub reason: String }
+#[derive(Debug, Default, Serialize)] pub struct FulfillmentImportResponse { pub updated: Vec<Uuid>, pub unmatched: Vec<Uuid>, pub rejected: Vec<RejectedUpdate> }
+
+const FULFILLMENT_STATUSES: [&str; 4] = ["unfulfilled", "partial", "fulfilled", "delivered"];
+
+/// Fulfillment only moves forward; unknown statuses are never accepted.
+fn fulfillment_transition_allowed(from: &str, to: &str) -> bool {
+    let rank = |s: &str| FULFILLMENT_STATUSES.iter().position(|&f| f == s);
+    matches!((rank(from), rank(to)), (Some(f), Some(t)) if t >= f)
+}
+
+/// Order `status` that goes with a fulfillment update, or the rejection reason for orders that must not ship.
+/// Mirrors `OrderStatus::can_transition_to`: only confirmed or processing orders ship, and a paid order counts as
+/// processing. `None` leaves the status alone; a delivered order is never moved back to shipped.
+fn order_status_for_fulfillment(order_status: &str, payment_status: &str, fulfillment: &str) -> Result<Option<&'static str>, String> {
+    let shippable = matches!(order_status, "confirmed" | "processing") || (order_status == "pending" && payment_status == "paid");
+    match (order_status, fulfillment) {
+        ("cancelled" | "refunded", _) => Err(format!("order is {}", order_status)),
+        ("shipped" | "delivered", "fulfilled") | ("delivered", "delivered") | (_, "unfulfilled") => Ok(None),
+        ("shipped", "delivered") => Ok(Some("delivered")),
+        _ if !shippable => Err(format!("order is {} and payment is {}", order_status, payment_status)),
+        (_, "fulfilled") => Ok(Some("shipped")),
+        (_, "delivered") => Ok(Some("delivered")),
+        _ => Ok(None),
+    }
+}
+
+/// Event announced when an order's fulfillment moves to `status`. 3PL reports are order-level, so a partial
+/// fulfillment carries no line ids.
+fn fulfillment_event(order_id: Uuid, status: &str, tracking: Option<String>) -> Option<OrderEvent> {
+    let order_id = order_id.to_string();
+    match status {
+        "partial" => Some(OrderEvent::PartiallyFulfilled { order_id, line_item_ids: vec![] }),
+        "fulfilled" => Some(OrderEvent::Shipped { order_id, tracking }),
+        "delivered" => Some(OrderEvent::Delivered { order_id }),
+        _ => None,
+    }
+}
+
+/// Applies 3PL status reports in one transaction, reporting unknown orders and rejected transitions instead of failing.
+/// Lifecycle events go out only once the whole batch has committed.
+async fn import_fulfillment(State(s): State<AppState>, Json(updates): Json<Vec<FulfillmentUpdate>>) -> Result<Json<FulfillmentImportResponse>, (StatusCode, String)> {
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let mut res = FulfillmentImportResponse::default();
+    let mut events = Vec::new();
+    for u in updates {
+        let current: Option<(String, String, String)> = sqlx::query_as("SELECT status, payment_status, fulfillment_status FROM orders WHERE id = $1 FOR UPDATE").bind(u.order_id).fetch_optional(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let Some((order_status, payment_status, from)) = current else { res.unmatched.push(u.order_id); continue };
+        let new_status = match order_status_for_fulfillment(&order_status, &payment_status, &u.status) {
+            Ok(new_status) => new_status,
+            Err(reason) => { res.rejected.push(RejectedUpdate { order_id: u.order_id, reason }); continue; }
+        };
+        if !fulfillment_transition_allowed(&from, &u.status) { res.rejected.push(RejectedUpdate { order_id: u.order_id, reason: format!("cannot move from {} to {}", from, u.status) }); continue; }
+        sqlx::query("UPDATE orders SET fulfillment_status = $2, status = COALESCE($5, status), tracking_number = COALESCE($3, tracking_number), carrier = COALESCE($4, carrier), updated_at = NOW() WHERE id = $1")
+            .bind(u.order_id).bind(&u.status).bind(&u.tracking).bind(&u.carrier).bind(new_status)
+            .execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if let Some(event) = fulfillment_event(u.order_id, &u.status, u.tracking.clone()).filter(|_| from != u.status) { events.push(event); }
+        res.updated.push(u.order_id);
+    }
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    for event in &events { publish_order_event(&s.nats, event).await; }
+    Ok(Json(res))
+}
+
+const MAX_SESSION_ID_LEN: usize = 64;
+
+/// Cart session ids are path segments and DB keys, so only bounded alphanumeric-and-dash ids are accepted.
+fn validate_session_id(session: &str) -> Result<(), (StatusCode, String)> {
+    if session.is_empty() || session.len() > MAX_SESSION_ID_LEN || !session.chars().all(|c| c.is_ascii_alphanumeric() || c == '-') {
+        return Err((StatusCode::BAD_REQUEST, format!("Invalid session id: expected 1-{} alphanumeric or '-' characters", MAX_SESSION_ID_LEN)));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)] pub struct AddressRequest { pub address: serde_json::Value, pub is_default: Option<bool> }
+
+/// The oldest remaining address inherits the default when the default one is deleted.
+fn next_default(remaining: &[CustomerAddress]) -> Option<Uuid> { remaining.iter().min_by_key(|a| a.created_at).map(|a| a.id) }
+
+async fn list_addresses(State(s): State<AppState>, Path(customer_id): Path<Uuid>) -> Result<Json<Vec<CustomerAddress>>, (StatusCode, String)> {
+    let addresses = sqlx::query_as::<_, CustomerAddress>("SELECT * FROM customer_addresses WHERE customer_id = $1 ORDER BY is_default DESC, created_at").bind(customer_id).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(addresses))
+}
+
+/// A customer's first address always becomes the default.
+async fn create_address(State(s): State<AppState>, Path(customer_id): Path<Uuid>, Json(r): Json<AddressRequest>) -> Result<(StatusCode, Json<CustomerAddress>), (StatusCode, String)> {
+    validate_address(&r.address).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (existing,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM customer_addresses WHERE customer_id = $1").bind(customer_id).fetch_one(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let is_default = existing == 0 || r.is_default.unwrap_or(false);
+    if is_default { sqlx::query("UPDATE customer_addresses SET is_default = FALSE WHERE customer_id = $1").bind(customer_id).execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?; }
+    let a = sqlx::query_as::<_, CustomerAddress>("INSERT INTO customer_addresses (id, customer_id, address, is_default, created_at) VALUES ($1, $2, $3, $4, NOW()) RETURNING *")
+        .bind(Uuid::now_v7()).bind(customer_id).bind(&r.address).bind(is_default)
+        .fetch_one(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok((StatusCode::CREATED, Json(a)))
+}
+
+async fn update_address(State(s): State<AppState>, Path((customer_id, address_id)): Path<(Uuid, Uuid)>, Json(r): Json<AddressRequest>) -> Result<Json<CustomerAddress>, (StatusCode, String)> {
+    validate_address(&r.address).map_err(|e| (StatusCode::UNPROCESSABLE_ENTITY, e))?;
+    let a = sqlx::query_as::<_, CustomerAddress>("UPDATE customer_addresses SET address = $3 WHERE id = $2 AND customer_id = $1 RETURNING *")
+        .bind(customer_id).bind(address_id).bind(&r.address)
+        .fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))?;
+    if r.is_default == Some(true) { return set_default_address(State(s), Path((customer_id, a.id))).await; }
+    Ok(Json(a))
+}
+
+async fn set_default_address(State(s): State<AppState>, Path((customer_id, address_id)): Path<(Uuid, Uuid)>) -> Result<Json<CustomerAddress>, (StatusCode, String)> {
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    sqlx::query("UPDATE customer_addresses SET is_default = FALSE WHERE customer_id = $1 AND id <> $2").bind(customer_id).bind(address_id).execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let a = sqlx::query_as::<_, CustomerAddress>("UPDATE customer_addresses SET is_default = TRUE WHERE id = $2 AND customer_id = $1 RETURNING *")
+        .bind(customer_id).bind(address_id)
+        .fetch_optional(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(a))
+}
+
+async fn delete_address(State(s): State<AppState>, Path((customer_id, address_id)): Path<(Uuid, Uuid)>) -> Result<StatusCode, (StatusCode, String)> {
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let deleted = sqlx::query_as::<_, CustomerAddress>("DELETE FROM customer_addresses WHERE id = $2 AND customer_id = $1 RETURNING *")
+        .bind(customer_id).bind(address_id)
+        .fetch_optional(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?.ok_or((StatusCode::NOT_FOUND, "Not found".to_string()))?;
+    if deleted.is_default {
+        let remaining = sqlx::query_as::<_, CustomerAddress>("SELECT * FROM customer_addresses WHERE customer_id = $1").bind(customer_id).fetch_all(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        if let Some(promoted) = next_default(&remaining) { sqlx::query("UPDATE customer_addresses SET is_default = TRUE WHERE id = $1").bind(promoted).execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?; }
+    }
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(StatusCode::NO_CONTENT)
 }
 
 async fn get_cart(State(s): State<AppState>, Path(session): Path<String>) -> Result<Json<Vec<CartItem>>, (StatusCode, String)> {
+    validate_session_id(&session)?;
     let items = sqlx::query_as::<_, CartItem>("SELECT * FROM cart_items WHERE session_id = $1").bind(&session).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok(Json(items))
 }
 
-#[derive(Debug, Deserialize)] pub struct AddToCartRequest { pub product_id: Uuid, pub quantity: i32 }
+#[derive(Debug, Deserialize)] pub struct AddToCartRequest { pub product_id: Uuid, #[serde(default)] pub variant_id: Option<Uuid>, pub quantity: i32 }
 
 async fn add_to_cart(State(s): State<AppState>, Path(session): Path<String>, Json(r): Json<AddToCartRequest>) -> Result<(StatusCode, Json<CartItem>), (StatusCode, String)> {
-    let item = sqlx::query_as::<_, CartItem>("INSERT INTO cart_items (id, session_id, product_id, quantity, created_at) VALUES ($1, $2, $3, $4, NOW()) ON CONFLICT (session_id, product_id) DO UPDATE SET quantity = cart_items.quantity + $4 RETURNING *")
-        .bind(Uuid::now_v7()).bind(&session).bind(r.product_id).bind(r.quantity)
+    validate_session_id(&session)?;
+    let variants = product_variant_ids(&s.db, r.product_id).await?;
+    check_variant_selection(&variants, r.variant_id)?;
+    let limit: Option<(Option<i32>, i32)> = sqlx::query_as("SELECT max_per_order, (SELECT COALESCE(SUM(quantity), 0)::int FROM cart_items WHERE session_id = $2 AND product_id = $1) FROM products WHERE id = $1")
+        .bind(r.product_id).bind(&session).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (max_per_order, in_cart) = limit.ok_or((StatusCode::NOT_FOUND, "Product not found".to_string()))?;
+    let quantity = limited_cart_quantity(in_cart, r.quantity, max_per_order, s.cart_quantity_limit)?;
+    let item = sqlx::query_as::<_, CartItem>("INSERT INTO cart_items (id, session_id, product_id, variant_id, quantity, unit_price, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, (SELECT price FROM products WHERE id = $3), NOW(), NOW()) ON CONFLICT (session_id, product_id, variant_id) DO UPDATE SET quantity = cart_items.quantity + $5, unit_price = EXCLUDED.unit_price, updated_at = NOW() RETURNING *")
+        .bind(Uuid::now_v7()).bind(&session).bind(r.product_id).bind(r.variant_id).bind(quantity)
         .fetch_one(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok((StatusCode::CREATED, Json(item)))
 }
 
+/// Quantity to add given a product's per-order maximum (counted across its variants already in the cart), mirroring
+/// `Cart::add_item_limited`.
+fn limited_cart_quantity(in_cart: i32, requested: i32, max_per_order: Option<i32>, limit: QuantityLimit) -> Result<i32, (StatusCode, String)> {
+    let Some(max) = max_per_order else { return Ok(requested) };
+    let room = (max - in_cart).max(0);
+    if requested <= room { return Ok(requested); }
+    if limit == QuantityLimit::Cap && room > 0 { return Ok(room); }
+    Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Limit of {} per order for this product ({} already in cart)", max, in_cart)))
+}
+
+async fn product_variant_ids(db: &sqlx::PgPool, product_id: Uuid) -> Result<Vec<Uuid>, (StatusCode, String)> {
+    let rows: Vec<(Uuid,)> = sqlx::query_as("SELECT id FROM product_variants WHERE product_id = $1").bind(product_id).fetch_all(db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(rows.into_iter().map(|r| r.0).collect())
+}
+
+/// A product with variants must be added as one of its own variants; a product without variants takes none.
+fn check_variant_selection(variants: &[Uuid], requested: Option<Uuid>) -> Result<(), (StatusCode, String)> {
+    match requested {
+        None if variants.is_empty() => Ok(()),
+        None => Err((StatusCode::UNPROCESSABLE_ENTITY, "variant_id is required for this product".to_string())),
+        Some(v) if variants.contains(&v) => Ok(()),
+        Some(_) => Err((StatusCode::UNPROCESSABLE_ENTITY, "Variant does not belong to product".to_string())),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)] #[serde(rename_all = "snake_case")] pub enum BatchLineStatus { Added, InsufficientStock, NotFound, InvalidVariant, LimitExceeded }
+/// `quantity` is what was actually added, which is less than requested when `max_per_order` capped the line.
+#[derive(Debug, Serialize)] pub struct BatchLineResult { pub product_id: Uuid, pub status: BatchLineStatus, pub quantity: i32 }
+#[derive(Debug, Serialize)] pub struct BatchAddResponse { pub items: Vec<CartItem>, pub results: Vec<BatchLineResult> }
+
+fn batch_line_status(available: Option<i32>, in_cart: i32, requested: i32) -> BatchLineStatus {
+    match available { None => BatchLineStatus::NotFound, Some(a) if in_cart + requested > a => BatchLineStatus::InsufficientStock, Some(_) => BatchLineStatus::Added }
+}
+
+/// `product` is `(inventory_quantity, max_per_order, quantity of the product already in the cart across variants)`.
+/// The per-order limit applies first, so the stock check sees the possibly capped quantity.
+fn batch_line_outcome(product: Option<(i32, Option<i32>, i32)>, in_cart: i32, requested: i32, limit: QuantityLimit) -> (BatchLineStatus, i32) {
+    let Some((available, max_per_order, product_in_cart)) = product else { return (BatchLineStatus::NotFound, 0) };
+    let Ok(quantity) = limited_cart_quantity(product_in_cart, requested, max_per_order, limit) else { return (BatchLineStatus::LimitExceeded, 0) };
+    match batch_line_status(Some(available), in_cart, quantity) { BatchLineStatus::Added => (BatchLineStatus::Added, quantity), status => (status, 0) }
+}
+
+/// Adds each line independently so one bad line never fails the whole batch.
+async fn add_to_cart_batch(State(s): State<AppState>, Path(session): Path<String>, Json(lines): Json<Vec<AddToCartRequest>>) -> Result<Json<BatchAddResponse>, (StatusCode, String)> {
+    validate_session_id(&session)?;
+    let mut results = Vec::with_capacity(lines.len());
+    for line in &lines {
+        let product: Option<(i32, Option<i32>, i32)> = sqlx::query_as("SELECT inventory_quantity, max_per_order, (SELECT COALESCE(SUM(quantity), 0)::int FROM cart_items WHERE session_id = $2 AND product_id = $1) FROM products WHERE id = $1 AND status = 'active'")
+            .bind(line.product_id).bind(&session).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let in_cart: Option<(i32,)> = sqlx::query_as("SELECT quantity FROM cart_items WHERE session_id = $1 AND product_id = $2 AND variant_id IS NOT DISTINCT FROM $3").bind(&session).bind(line.product_id).bind(line.variant_id).fetch_optional(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        let (mut status, mut quantity) = batch_line_outcome(product, in_cart.map_or(0, |c| c.0), line.quantity, s.cart_quantity_limit);
+        if status == BatchLineStatus::Added && check_variant_selection(&product_variant_ids(&s.db, line.product_id).await?, line.variant_id).is_err() { (status, quantity) = (BatchLineStatus::InvalidVariant, 0); }
+        if status == BatchLineStatus::Added {
+            sqlx::query("INSERT INTO cart_items (id, session_id, product_id, variant_id, quantity, unit_price, created_at, updated_at) VALUES ($1, $2, $3, $4, $5, (SELECT price FROM products WHERE id = $3), NOW(), NOW()) ON CONFLICT (session_id, product_id, variant_id) DO UPDATE SET quantity = cart_items.quantity + $5, unit_price = EXCLUDED.unit_price, updated_at = NOW()")
+                .bind(Uuid::now_v7()).bind(&session).bind(line.product_id).bind(line.variant_id).bind(quantity)
+                .execute(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+        }
+        results.push(BatchLineResult { product_id: line.product_id, status, quantity });
+    }
+    let items = sqlx::query_as::<_, CartItem>("SELECT * FROM cart_items WHERE session_id = $1").bind(&session).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    Ok(Json(BatchAddResponse { items, results }))
+}
+
+#[derive(Debug, Default, Deserialize)] pub struct CartValidationRequest { pub shipping_address: Option<serde_json::Value> }
+/// `price` and friends are `None` when the product row no longer exists.
+#[derive(Debug, Clone, sqlx::FromRow)] pub struct CartValidationLine { pub product_id: Uuid, pub quantity: i32, pub added_price: Option<i64>, pub price: Option<i64>, pub currency: Option<String>, pub inventory_quantity: Option<i32>, pub status: Option<String> }
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)] #[serde(tag = "type", rename_all = "snake_case")]
+pub enum CartIssue { EmptyCart, Unavailable { product_id: Uuid }, OutOfStock { product_id: Uuid, requested: i32, available: i32 }, PriceChanged { product_id: Uuid, was: i64, now: i64 }, MixedCurrency { currencies: Vec<String> }, InvalidAddress { message: String } }
+#[derive(Debug, Serialize)] pub struct CartValidation { pub ready: bool, pub issues: Vec<CartIssue> }
+
+fn cart_issues(lines: &[CartValidationLine], shipping_address: Option<&serde_json::Value>) -> Vec<CartIssue> {
+    let mut issues = Vec::new();
+    if lines.is_empty() { issues.push(CartIssue::EmptyCart); }
+    for l in lines {
+        let (Some(price), Some(available)) = (l.price, l.inventory_quantity) else { issues.push(CartIssue::Unavailable { product_id: l.product_id }); continue };
+        if l.status.as_deref() != Some("active") { issues.push(CartIssue::Unavailable { product_id: l.product_id }); continue; }
+        if available < l.quantity { issues.push(CartIssue::OutOfStock { product_id: l.product_id, requested: l.quantity, available }); }
+        if let Some(was) = l.added_price.filter(|&was| was != price) { issues.push(CartIssue::PriceChanged { product_id: l.product_id, was, now: price }); }
+    }
+    let mut currencies: Vec<String> = lines.iter().filter_map(|l| l.currency.clone()).collect();
+    currencies.sort(); currencies.dedup();
+    if currencies.len() > 1 { issues.push(CartIssue::MixedCurrency { currencies }); }
+    if let Some(Err(message)) = shipping_address.map(validate_address) { issues.push(CartIssue::InvalidAddress { message }); }
+    issues
+}
+
+/// Read-only checkout readiness check; an empty issue list means the cart can go to checkout.
+async fn validate_cart(State(s): State<AppState>, Path(session): Path<String>, body: Option<Json<CartValidationRequest>>) -> Result<Json<CartValidation>, (StatusCode, String)> {
+    validate_session_id(&session)?;
+    let r = body.map(|Json(r)| r).unwrap_or_default();
+    let lines = sqlx::query_as::<_, CartValidationLine>("SELECT c.product_id, c.quantity, c.unit_price AS added_price, p.price, p.currency, p.inventory_quantity, p.status FROM cart_items c LEFT JOIN products p ON p.id = c.product_id WHERE c.session_id = $1")
+        .bind(&session).fetch_all(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let issues = cart_issues(&lines, r.shipping_address.as_ref());
+    Ok(Json(CartValidation { ready: issues.is_empty(), issues }))
+}
+
 async fn clear_cart(State(s): State<AppState>, Path(session): Path<String>) -> Result<StatusCode, (StatusCode, String)> {
+    validate_session_id(&session)?;
     sqlx::query("DELETE FROM cart_items WHERE session_id = $1").bind(&session).execute(&s.db).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
     Ok(StatusCode::NO_CONTENT)
 }
 
-async fn checkout(State(_s): State<AppState>, Json(_r): Json<serde_json::Value>) -> impl IntoResponse {
-    Json(serde_json::json!({"status": "checkout_initiated", "message": "Implement payment integration"}))
+#[derive(Debug, Deserialize)] pub struct CheckoutRequest { pub session_id: String, pub customer_email: String, pub shipping_address: serde_json::Value }
+#[derive(Debug, Clone, sqlx::FromRow)] pub struct CheckoutLine { pub product_id: Uuid, pub sku: String, pub name: String, pub price: i64, pub currency: String, pub inventory_quantity: i32, pub quantity: i32 }
+
+fn find_shortage(lines: &[CheckoutLine]) -> Option<&CheckoutLine> { lines.iter().find(|l| l.inventory_quantity < l.quantity) }
+fn checkout_subtotal(lines: &[CheckoutLine]) -> i64 { lines.iter().map(|l| l.price * l.quantity as i64).sum() }
+
+/// An order is priced in a single currency; lines in several are rejected rather than summed.
+fn order_currency(lines: &[CheckoutLine]) -> Result<&str, (StatusCode, String)> {
+    let mut currencies: Vec<&str> = lines.iter().map(|l| l.currency.as_str()).collect();
+    currencies.sort_unstable();
+    currencies.dedup();
+    match currencies[..] {
+        [currency] => Ok(currency),
+        [] => Err((StatusCode::UNPROCESSABLE_ENTITY, "Order has no items".to_string())),
+        _ => Err((StatusCode::UNPROCESSABLE_ENTITY, format!("Order mixes currencies: {}", currencies.join(", ")))),
+    }
+}
+
+/// Turns the session cart into an order in one transaction: stock is locked and checked, the order and its items are
+/// written, inventory is decremented, and the cart is cleared. Any failure rolls the whole checkout back.
+async fn checkout(State(s): State<AppState>, Json(r): Json<CheckoutRequest>) -> Result<(StatusCode, Json<OrderResponse>), (StatusCode, String)> {
+    validate_session_id(&r.session_id)?;
+    s.checkout_fields.validate(&r.customer_email, &r.shipping_address)?;
+    if !s.order_limiter.allows(&r.customer_email, Utc::now()) { return Err((StatusCode::TOO_MANY_REQUESTS, "Too many orders for this email, try again later".to_string())); }
+    let mut tx = s.db.begin().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let lines = sqlx::query_as::<_, CheckoutLine>("SELECT p.id AS product_id, p.sku, p.name, p.price, p.currency, p.inventory_quantity, c.quantity FROM cart_items c JOIN products p ON p.id = c.product_id AND p.status = 'active' WHERE c.session_id = $1 ORDER BY p.id FOR UPDATE OF p")
+        .bind(&r.session_id).fetch_all(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    let (cart_lines,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM cart_items WHERE session_id = $1").bind(&r.session_id).fetch_one(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    if cart_lines == 0 { return Err((StatusCode::UNPROCESSABLE_ENTITY, "Cart is empty".to_string())); }
+    if cart_lines != lines.len() as i64 { return Err((StatusCode::CONFLICT, "Cart contains products that are no longer available".to_string())); }
+    if let Some(l) = find_shortage(&lines) { return Err((StatusCode::CONFLICT, format!("Insufficient inventory for SKU {}: requested {}, available {}", l.sku, l.quantity, l.inventory_quantity))); }
+    for l in &lines {
+        sqlx::query("UPDATE products SET inventory_quantity = inventory_quantity - $2, updated_at = NOW() WHERE id = $1").bind(l.product_id).bind(l.quantity).execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    }
+    let order = insert_order(&mut tx, &s.tax, &r.customer_email, &r.shipping_address, &lines).await?;
+    sqlx::query("DELETE FROM cart_items WHERE session_id = $1").bind(&r.session_id).execute(&mut *tx).await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    tx.commit().await.map_err(|e| (StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?;
+    s.order_limiter.record(&r.customer_email, Utc::now());
+    publish_order_event(&s.nats, &order_created_event(&order)).await;
+    Ok((StatusCode::CREATED, Json(order.into())))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    /// Database-backed tests are `#[ignore]`d; run them with `cargo test -- --ignored` and `DATABASE_URL` pointing at a
+    /// scratch Postgres.
+    async fn test_state() -> AppState {
+        let db = PgPoolOptions::new().max_connections(5).connect(&std::env::var("DATABASE_URL").expect("DATABASE_URL")).await.unwrap();
+        sqlx::migrate!("./migrations").run(&db).await.unwrap();
+        AppState { db, nats: None, hide_out_of_stock: false, auto_compare_at_price: false, order_limiter: OrderRateLimiter::new(1000, chrono::Duration::hours(1)), image_cdn_base: None, image_cdn_params: None, checkout_fields: CheckoutFieldConfig::default(), cart_quantity_limit: QuantityLimit::Reject, tax: RegionalTax::new(), cart_ttl: chrono::Duration::hours(72) }
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_hide_out_of_stock_keeps_sellable_products() {
+        let state = AppState { hide_out_of_stock: true, ..test_state().await };
+        let category = Uuid::now_v7();
+        sqlx::query("INSERT INTO categories (id, name, slug) VALUES ($1, 'Hide test', $2)").bind(category).bind(category.to_string()).execute(&state.db).await.unwrap();
+        for (name, policy, preorder_until) in [("deny", "deny", None), ("continue", "continue", None), ("preorder", "deny", Some(Utc::now() + chrono::Duration::days(30)))] {
+            sqlx::query("INSERT INTO products (id, sku, name, price, category_id, inventory_quantity, inventory_policy, preorder_until) VALUES ($1, $2, $3, 1000, $4, 0, $5, $6)")
+                .bind(Uuid::now_v7()).bind(format!("{}-{}", name, category)).bind(name).bind(category).bind(policy).bind(preorder_until).execute(&state.db).await.unwrap();
+        }
+        let params = ListParams { page: None, per_page: None, category: Some(category), search: None, sort: Some("name_asc".into()) };
+        let Json(listed) = list_products(State(state), Query(params), Query(vec![])).await.unwrap();
+        assert_eq!(listed.data.iter().map(|p| p.name.as_str()).collect::<Vec<_>>(), ["continue", "preorder"]);
+    }
+    async fn insert_test_product(db: &sqlx::PgPool, currency: &str, inventory_quantity: i32) -> Uuid {
+        let id = Uuid::now_v7();
+        sqlx::query("INSERT INTO products (id, sku, name, price, currency, inventory_quantity) VALUES ($1, $2, 'Test product', 1000, $3, $4)")
+            .bind(id).bind(id.to_string()).bind(currency).bind(inventory_quantity).execute(db).await.unwrap();
+        id
+    }
+    fn order_request(items: Vec<OrderItemRequest>) -> CreateOrderRequest {
+        CreateOrderRequest { customer_email: "buyer@example.com".into(), items, shipping_address: serde_json::json!({}) }
+    }
+    async fn insert_test_category(db: &sqlx::PgPool) -> Uuid {
+        let id = Uuid::now_v7();
+        sqlx::query("INSERT INTO categories (id, name, slug) VALUES ($1, $2, $2)").bind(id).bind(id.to_string()).execute(db).await.unwrap();
+        id
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_moving_product_updates_category_counts() {
+        let state = test_state().await;
+        let (shoes, bags) = (insert_test_category(&state.db).await, insert_test_category(&state.db).await);
+        let product_id = insert_test_product(&state.db, "NGN", 5).await;
+        sqlx::query("UPDATE products SET category_id = $2 WHERE id = $1").bind(product_id).bind(shoes).execute(&state.db).await.unwrap();
+        let count = |id| { let state = state.clone(); async move { get_category(State(state), Path(id)).await.unwrap().0.product_count } };
+        assert_eq!((count(shoes).await, count(bags).await), (1, 0));
+        let moved = CreateProductRequest { name: "Test product".into(), description: None, price: 1000, cost: None, category_id: Some(bags), inventory_quantity: Some(5) };
+        update_product(State(state.clone()), Path(product_id), Json(moved)).await.unwrap();
+        assert_eq!((count(shoes).await, count(bags).await), (0, 1));
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_concurrent_orders_never_oversell() {
+        let state = test_state().await;
+        let product_id = insert_test_product(&state.db, "NGN", 5).await;
+        let attempts = (0..10).map(|_| create_order(State(state.clone()), HeaderMap::new(), Json(order_request(vec![OrderItemRequest { product_id, quantity: 1 }]))));
+        let results = futures::future::join_all(attempts).await;
+        assert_eq!(results.iter().filter(|r| r.is_ok()).count(), 5);
+        assert!(results.iter().filter_map(|r| r.as_ref().err()).all(|(status, _)| *status == StatusCode::CONFLICT));
+        let (left,): (i32,) = sqlx::query_as("SELECT inventory_quantity FROM products WHERE id = $1").bind(product_id).fetch_one(&state.db).await.unwrap();
+        assert_eq!(left, 0);
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_mark_order_paid_once() {
+        let state = test_state().await;
+        let product_id = insert_test_product(&state.db, "NGN", 5).await;
+        let (_, Json(order)) = create_order(State(state.clone()), HeaderMap::new(), Json(order_request(vec![OrderItemRequest { product_id, quantity: 1 }]))).await.unwrap();
+        let Json(paid) = mark_order_paid(State(state.clone()), Path(order.order.id)).await.unwrap();
+        assert_eq!(paid.order.payment_status, "paid");
+        assert_eq!(mark_order_paid(State(state.clone()), Path(order.order.id)).await.unwrap_err().0, StatusCode::CONFLICT);
+        assert_eq!(mark_order_paid(State(state), Path(Uuid::now_v7())).await.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_cancel_stale_unpaid_restores_stock() {
+        let state = test_state().await;
+        let product_id = insert_test_product(&state.db, "NGN", 5).await;
+        let mut placed = Vec::new();
+        for _ in 0..2 {
+            let (_, Json(o)) = create_order(State(state.clone()), HeaderMap::new(), Json(order_request(vec![OrderItemRequest { product_id, quantity: 2 }]))).await.unwrap();
+            placed.push(o.order.id);
+        }
+        let (unpaid, paid) = (placed[0], placed[1]);
+        mark_order_paid(State(state.clone()), Path(paid)).await.unwrap();
+        let cancelled = cancel_stale_unpaid(&state.db, chrono::Duration::hours(1), Utc::now() + chrono::Duration::hours(2)).await.unwrap();
+        assert!(cancelled.contains(&unpaid));
+        assert!(!cancelled.contains(&paid));
+        let status = |id| { let db = state.db.clone(); async move { sqlx::query_as::<_, (String,)>("SELECT status FROM orders WHERE id = $1").bind(id).fetch_one(&db).await.unwrap().0 } };
+        assert_eq!((status(unpaid).await, status(paid).await), ("cancelled".to_string(), "pending".to_string()));
+        let (left,): (i32,) = sqlx::query_as("SELECT inventory_quantity FROM products WHERE id = $1").bind(product_id).fetch_one(&state.db).await.unwrap();
+        assert_eq!(left, 3);
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_restore_deleted_product_as_draft() {
+        let state = test_state().await;
+        let product_id = insert_test_product(&state.db, "NGN", 5).await;
+        assert_eq!(delete_product(State(state.clone()), Path(product_id)).await.unwrap(), StatusCode::NO_CONTENT);
+        let Json(restored) = restore_product(State(state.clone()), Path(product_id)).await.unwrap();
+        assert_eq!(restored.status, "draft");
+        assert_eq!(restore_product(State(state.clone()), Path(product_id)).await.unwrap_err().0, StatusCode::CONFLICT);
+        assert_eq!(restore_product(State(state), Path(Uuid::now_v7())).await.unwrap_err().0, StatusCode::NOT_FOUND);
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_stock_take_records_variance() {
+        let state = test_state().await;
+        let product_id = insert_test_product(&state.db, "NGN", 10).await;
+        let Json(taken) = stock_take(State(state.clone()), Path(product_id), Json(StockTakeRequest { counted_quantity: 8 })).await.unwrap();
+        assert_eq!((taken.previous_quantity, taken.variance), (10, -2));
+        let adjustment: (i32, i32, i32, String) = sqlx::query_as("SELECT previous_quantity, new_quantity, variance, reason FROM inventory_adjustments WHERE product_id = $1").bind(product_id).fetch_one(&state.db).await.unwrap();
+        assert_eq!(adjustment, (10, 8, -2, "stock_take".to_string()));
+        let (on_hand,): (i32,) = sqlx::query_as("SELECT inventory_quantity FROM products WHERE id = $1").bind(product_id).fetch_one(&state.db).await.unwrap();
+        assert_eq!(on_hand, 8);
+    }
+    async fn insert_named_product(db: &sqlx::PgPool, name: &str, description: &str, category_id: Option<Uuid>) -> Uuid {
+        let id = Uuid::now_v7();
+        sqlx::query("INSERT INTO products (id, sku, name, description, price, category_id, inventory_quantity) VALUES ($1, $2, $3, $4, 1000, $5, 5)")
+            .bind(id).bind(id.to_string()).bind(name).bind(description).bind(category_id).execute(db).await.unwrap();
+        id
+    }
+    async fn listed_names(state: &AppState, category: Option<Uuid>, search: Option<String>, sort: Option<&str>) -> Vec<String> {
+        let params = ListParams { page: None, per_page: Some(100), category, search, sort: sort.map(String::from) };
+        let Json(listed) = list_products(State(state.clone()), Query(params), Query(vec![])).await.unwrap();
+        listed.data.into_iter().map(|p| p.name).collect()
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_search_ranks_name_matches_first() {
+        let state = test_state().await;
+        let token = Uuid::now_v7().simple().to_string();
+        // The name match is older, so newest-first alone would list the description match first.
+        insert_named_product(&state.db, &format!("{} mug", token), "", None).await;
+        insert_named_product(&state.db, "Plain cup", &format!("Pairs with the {} mug", token), None).await;
+        assert_eq!(listed_names(&state, None, Some(token.clone()), None).await, [format!("{} mug", token), "Plain cup".to_string()]);
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_category_filter_includes_memberships() {
+        let state = test_state().await;
+        let (shoes, bags) = (insert_test_category(&state.db).await, insert_test_category(&state.db).await);
+        insert_named_product(&state.db, "Primary", "", Some(shoes)).await;
+        let member = insert_named_product(&state.db, "Member", "", Some(bags)).await;
+        insert_named_product(&state.db, "Elsewhere", "", Some(bags)).await;
+        sqlx::query("INSERT INTO product_categories (product_id, category_id) VALUES ($1, $2)").bind(member).bind(shoes).execute(&state.db).await.unwrap();
+        assert_eq!(listed_names(&state, Some(shoes), None, Some("name_asc")).await, ["Member", "Primary"]);
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_manual_category_order() {
+        let state = test_state().await;
+        let category = insert_test_category(&state.db).await;
+        let mut ids = Vec::new();
+        for name in ["A", "B", "C"] { ids.push(insert_named_product(&state.db, name, "", Some(category)).await); }
+        let status = reorder_category_products(State(state.clone()), Path(category), Json(ReorderProductsRequest { product_ids: vec![ids[2], ids[0], ids[1]] })).await.unwrap();
+        assert_eq!(status, StatusCode::NO_CONTENT);
+        assert_eq!(listed_names(&state, Some(category), None, Some("manual")).await, ["C", "A", "B"]);
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_stock_breakdown_ignores_expired_carts() {
+        let state = test_state().await;
+        let product_id = insert_test_product(&state.db, "NGN", 10).await;
+        for (session, quantity, idle) in [("live", 2, chrono::Duration::hours(1)), ("abandoned", 5, state.cart_ttl + chrono::Duration::hours(1))] {
+            sqlx::query("INSERT INTO cart_items (id, session_id, product_id, quantity, unit_price, created_at, updated_at) VALUES ($1, $2, $3, $4, 1000, $5, $5)")
+                .bind(Uuid::now_v7()).bind(format!("{}-{}", session, product_id)).bind(product_id).bind(quantity).bind(Utc::now() - idle).execute(&state.db).await.unwrap();
+        }
+        let Json(breakdown) = get_stock_breakdown(State(state), Path(product_id)).await.unwrap();
+        assert_eq!((breakdown.reserved, breakdown.available), (2, 8));
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_idempotent_order_replay() {
+        let state = test_state().await;
+        let product_id = insert_test_product(&state.db, "NGN", 5).await;
+        let mut headers = HeaderMap::new();
+        headers.insert("idempotency-key", Uuid::now_v7().to_string().parse().unwrap());
+        let place = || create_order(State(state.clone()), headers.clone(), Json(order_request(vec![OrderItemRequest { product_id, quantity: 2 }])));
+        let (_, Json(first)) = place().await.unwrap();
+        let (status, Json(retry)) = place().await.unwrap();
+        assert_eq!((status, retry.order.id), (StatusCode::CREATED, first.order.id));
+        let (orders,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM order_items WHERE product_id = $1").bind(product_id).fetch_one(&state.db).await.unwrap();
+        assert_eq!(orders, 1);
+        let (left,): (i32,) = sqlx::query_as("SELECT inventory_quantity FROM products WHERE id = $1").bind(product_id).fetch_one(&state.db).await.unwrap();
+        assert_eq!(left, 3);
+    }
+    #[tokio::test]
+    #[ignore = "needs DATABASE_URL"]
+    async fn test_mixed_currency_order_rejected() {
+        let state = test_state().await;
+        let (naira, dollars) = (insert_test_product(&state.db, "NGN", 5).await, insert_test_product(&state.db, "USD", 5).await);
+        let items = vec![OrderItemRequest { product_id: naira, quantity: 1 }, OrderItemRequest { product_id: dollars, quantity: 1 }];
+        let (status, _) = create_order(State(state.clone()), HeaderMap::new(), Json(order_request(items))).await.unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        let (left,): (i32,) = sqlx::query_as("SELECT inventory_quantity FROM products WHERE id = $1").bind(naira).fetch_one(&state.db).await.unwrap();
+        assert_eq!(left, 5, "stock is restored when the order is rejected");
+    }
+    fn validation_line(quantity: i32, inventory_quantity: i32) -> CartValidationLine {
+        CartValidationLine { product_id: Uuid::nil(), quantity, added_price: Some(1000), price: Some(1000), currency: Some("NGN".into()), inventory_quantity: Some(inventory_quantity), status: Some("active".into()) }
+    }
+    #[test]
+    fn test_cart_issues() {
+        assert!(cart_issues(&[validation_line(2, 5)], None).is_empty());
+        assert_eq!(cart_issues(&[validation_line(2, 5), validation_line(3, 1)], None), vec![CartIssue::OutOfStock { product_id: Uuid::nil(), requested: 3, available: 1 }]);
+        assert_eq!(cart_issues(&[], None), vec![CartIssue::EmptyCart]);
+        let repriced = CartValidationLine { price: Some(1200), ..validation_line(1, 5) };
+        assert_eq!(cart_issues(&[repriced], None), vec![CartIssue::PriceChanged { product_id: Uuid::nil(), was: 1000, now: 1200 }]);
+        assert!(matches!(cart_issues(&[validation_line(1, 5)], Some(&serde_json::json!({"city": "Lagos"})))[..], [CartIssue::InvalidAddress { .. }]));
+    }
+    #[test]
+    fn test_checkout_field_config() {
+        let address = serde_json::json!({"street1": "1 Marina", "city": "Lagos"});
+        assert!(CheckoutFieldConfig::default().validate("a@example.com", &address).is_ok());
+        assert_eq!(CheckoutFieldConfig::default().validate(" ", &address).unwrap_err().0, StatusCode::UNPROCESSABLE_ENTITY);
+        let with_phone = CheckoutFieldConfig::parse("email, phone");
+        assert_eq!(with_phone.validate("a@example.com", &address).unwrap_err(), (StatusCode::UNPROCESSABLE_ENTITY, "Missing required fields: phone".to_string()));
+        assert!(with_phone.validate("a@example.com", &serde_json::json!({"phone": "+2348012345678"})).is_ok());
+    }
+    #[test]
+    fn test_stock_breakdown() {
+        assert_eq!(stock_breakdown(10, 4, 3), StockBreakdown { on_hand: 10, committed: 4, reserved: 3, available: 7 });
+        assert_eq!(stock_breakdown(2, 0, 5).available, 0);
+    }
+    #[derive(Default)] struct RecordingPublisher { sent: std::sync::Mutex<Vec<(String, serde_json::Value)>>, fail: bool }
+    impl EventPublisher for RecordingPublisher {
+        async fn publish_bytes(&self, subject: String, payload: Vec<u8>) -> Result<(), String> {
+            if self.fail { return Err("connection closed".into()); }
+            self.sent.lock().unwrap().push((subject, serde_json::from_slice(&payload).unwrap()));
+            Ok(())
+        }
+    }
+    #[test]
+    fn test_destination_tax_follows_address() {
+        let tax = parse_tax_rates("NG=0.075, GH=0.15, bogus, US-CA=x");
+        let lagos = serde_json::json!({"city": "Lagos", "country": "NG"});
+        let accra = serde_json::json!({"city": "Accra", "country": "GH"});
+        assert_eq!(destination_tax(&tax, 10_000, "NGN", &lagos), 750);
+        assert_eq!(destination_tax(&tax, 10_000, "NGN", &accra), 1_500);
+        assert_eq!(destination_tax(&tax, 10_000, "NGN", &serde_json::json!({"country": "US", "state": "CA"})), 0);
+        assert_eq!(destination_tax(&tax, 999, "NGN", &lagos), 75);
+    }
+    #[test]
+    fn test_unorderable_line() {
+        let id = Uuid::nil();
+        assert_eq!(unorderable_line(id, None).0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert_eq!(unorderable_line(id, Some(("TEE-1".into(), "archived".into()))), (StatusCode::UNPROCESSABLE_ENTITY, "Product TEE-1 is not available for sale".to_string()));
+        assert_eq!(unorderable_line(id, Some(("TEE-1".into(), "active".into()))).0, StatusCode::CONFLICT);
+    }
+    #[test]
+    fn test_pagination_metadata() {
+        let page = |total, page, per_page| { let r = PaginatedResponse::<()>::new(vec![], total, page, per_page); (r.total_pages, r.has_next) };
+        assert_eq!(page(40, 1, 20), (2, true));
+        assert_eq!(page(40, 2, 20), (2, false));
+        assert_eq!(page(41, 2, 20), (3, true));
+        assert_eq!(page(0, 1, 20), (0, false));
+        assert_eq!(page(5, 7, 20), (1, false));
+    }
+    #[test]
+    fn test_publish_event() {
+        let nats = Some(RecordingPublisher::default());
+        let shipped = fulfillment_event(Uuid::nil(), "fulfilled", Some("TRK1".into())).unwrap();
+        futures::executor::block_on(publish_order_event(&nats, &shipped));
+        futures::executor::block_on(publish_order_event(&nats, &OrderEvent::Paid { order_id: Uuid::nil().to_string() }));
+        assert_eq!(*nats.as_ref().unwrap().sent.lock().unwrap(), [
+            ("ecommerce.order.shipped".to_string(), serde_json::json!({"type": "Shipped", "order_id": Uuid::nil(), "tracking": "TRK1"})),
+            ("ecommerce.order.paid".to_string(), serde_json::json!({"type": "Paid", "order_id": Uuid::nil()})),
+        ]);
+        futures::executor::block_on(publish_order_event(&None::<RecordingPublisher>, &shipped));
+        futures::executor::block_on(publish_order_event(&Some(RecordingPublisher { fail: true, ..Default::default() }), &shipped));
+        assert!(fulfillment_event(Uuid::nil(), "unfulfilled", None).is_none());
+        assert_eq!(order_event_subject(&fulfillment_event(Uuid::nil(), "partial", None).unwrap()), "ecommerce.order.partially_fulfilled");
+    }
+    #[test]
+    fn test_cart_max_per_order() {
+        assert_eq!(limited_cart_quantity(0, 5, None, QuantityLimit::Reject), Ok(5));
+        assert_eq!(limited_cart_quantity(1, 1, Some(2), QuantityLimit::Reject), Ok(1));
+        let (status, message) = limited_cart_quantity(1, 3, Some(2), QuantityLimit::Reject).unwrap_err();
+        assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(message.contains("Limit of 2"));
+        assert_eq!(limited_cart_quantity(1, 3, Some(2), QuantityLimit::Cap), Ok(1));
+        assert!(limited_cart_quantity(2, 1, Some(2), QuantityLimit::Cap).is_err());
+    }
+    #[test]
+    fn test_order_items_csv_one_row_per_line() {
+        let row = |sku: &str, name: &str, quantity: i32, unit_price: i64| ExportItemRow { order_number: "ORD-1001".into(), currency: "NGN".into(), sku: sku.into(), name: name.into(), quantity, unit_price, total: unit_price * quantity as i64 };
+        let csv = order_items_csv(&[row("TEE-1", "Tee", 2, 1250), row("CAP-1", "Cap, blue", 1, 999)]);
+        let lines: Vec<_> = csv.lines().collect();
+        assert_eq!(lines, [
+            "order_number,sku,name,quantity,unit_price,line_total,currency",
+            "ORD-1001,TEE-1,Tee,2,12.50,25.00,NGN",
+            "ORD-1001,CAP-1,\"Cap, blue\",1,9.99,9.99,NGN",
+        ]);
+    }
+    #[test]
+    fn test_idempotency_key_header() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(idempotency_key(&headers).unwrap(), None);
+        headers.insert("Idempotency-Key", " retry-42 ".parse().unwrap());
+        assert_eq!(idempotency_key(&headers).unwrap().as_deref(), Some("retry-42"));
+        headers.insert("Idempotency-Key", "".parse().unwrap());
+        assert_eq!(idempotency_key(&headers).unwrap_err().0, StatusCode::BAD_REQUEST);
+        headers.insert("Idempotency-Key", "k".repeat(256).parse().unwrap());
+        assert!(idempotency_key(&headers).is_err());
+    }
+    #[test]
+    fn test_category_breadcrumb_is_root_first() {
+        let cat = |name: &str, parent_id: Option<Uuid>| Category { id: Uuid::new_v4(), name: name.into(), slug: slugify(name), description: None, parent_id, image_url: None, created_at: Utc::now(), product_count: 0 };
+        let root = cat("Clothing", None);
+        let mid = cat("Shoes", Some(root.id));
+        let leaf = cat("Sneakers", Some(mid.id));
+        let leaf_id = leaf.id;
+        let names: Vec<_> = breadcrumb(leaf_id, vec![leaf, root, mid]).into_iter().map(|c| c.name).collect();
+        assert_eq!(names, ["Clothing", "Shoes", "Sneakers"]);
+        assert!(breadcrumb(Uuid::new_v4(), vec![]).is_empty());
+    }
+    #[test]
+    fn test_category_cycle_detection() {
+        let (grandparent, parent, grandchild) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        assert!(creates_cycle(grandparent, grandchild, &[grandchild, parent, grandparent]));
+        assert!(creates_cycle(parent, parent, &[parent, grandparent]));
+        assert!(!creates_cycle(grandchild, grandparent, &[grandparent]));
+    }
+    #[test]
+    fn test_category_slug_and_delete_guard() {
+        assert_eq!(slugify("Men's  Shoes & Boots"), "men-s-shoes-boots");
+        assert_eq!(category_delete_blocker(0, 0), None);
+        assert_eq!(category_delete_blocker(3, 0).unwrap(), "Category still has 3 product(s)");
+        assert_eq!(category_delete_blocker(0, 1).unwrap(), "Category still has 1 child category");
+    }
+    #[test]
+    fn test_bulk_tag_inputs() {
+        let tags: Vec<String> = [" holiday-2024", "vip", "", "holiday-2024 "].iter().map(|t| t.to_string()).collect();
+        assert_eq!(normalize_tags(&tags), vec!["holiday-2024", "vip"]);
+        let (known, missing) = (Uuid::new_v4(), Uuid::new_v4());
+        let found = vec![OrderTags { id: known, tags: vec!["holiday-2024".into()] }];
+        assert_eq!(unknown_ids(&[known, missing, missing], &found), vec![missing]);
+    }
+    #[test]
+    fn test_order_number_check_digit() {
+        let number = generate_order_number(12345678);
+        assert_eq!(number, "ORD-123456782");
+        assert!(validate_order_number(&number));
+        assert!(validate_order_number("123456782"));
+        assert!(!validate_order_number("ORD-213456782"));
+        assert!(!validate_order_number("ORD-123456783"));
+        assert!(!validate_order_number("ORD-12A456782"));
+        assert!(!validate_order_number("ORD-"));
+        assert!(!validate_order_number("ORD-1é"));
+        assert!(!validate_order_number("ORD-12345678٢"));
+    }
+    #[test]
+    fn test_variant_selection() {
+        let variant = Uuid::new_v4();
+        assert_eq!(check_variant_selection(&[variant], None).unwrap_err().0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(check_variant_selection(&[variant], Some(variant)).is_ok());
+        assert_eq!(check_variant_selection(&[variant], Some(Uuid::new_v4())).unwrap_err().0, StatusCode::UNPROCESSABLE_ENTITY);
+        assert!(check_variant_selection(&[], None).is_ok());
+        assert!(check_variant_selection(&[], Some(variant)).is_err());
+    }
+    #[test]
+    fn test_batch_line_status() {
+        let lines = [(Some(10), 0, 2), (Some(0), 0, 1), (Some(5), 2, 3)];
+        let statuses: Vec<_> = lines.iter().map(|&(a, c, r)| batch_line_status(a, c, r)).collect();
+        assert_eq!(statuses, vec![BatchLineStatus::Added, BatchLineStatus::InsufficientStock, BatchLineStatus::Added]);
+        assert_eq!(batch_line_status(None, 0, 1), BatchLineStatus::NotFound);
+    }
+    #[test]
+    fn test_batch_line_outcome_applies_max_per_order() {
+        // Two of a max-three product are already in the cart (another variant), one of them on this line.
+        let product = Some((10, Some(3), 2));
+        assert_eq!(batch_line_outcome(product, 1, 1, QuantityLimit::Reject), (BatchLineStatus::Added, 1));
+        assert_eq!(batch_line_outcome(product, 1, 4, QuantityLimit::Reject), (BatchLineStatus::LimitExceeded, 0));
+        assert_eq!(batch_line_outcome(product, 1, 4, QuantityLimit::Cap), (BatchLineStatus::Added, 1));
+        assert_eq!(batch_line_outcome(Some((10, Some(3), 3)), 0, 1, QuantityLimit::Cap), (BatchLineStatus::LimitExceeded, 0));
+        assert_eq!(batch_line_outcome(Some((1, Some(3), 0)), 0, 2, QuantityLimit::Cap), (BatchLineStatus::InsufficientStock, 0));
+        assert_eq!(batch_line_outcome(None, 0, 1, QuantityLimit::Reject), (BatchLineStatus::NotFound, 0));
+    }
+    #[test]
+    fn test_product_sort_clause() {
+        assert!(product_sort_clause(Some("manual"), true).starts_with("pc.sort_order ASC NULLS LAST"));
+        assert_eq!(product_sort_clause(Some("manual"), false), "p.created_at DESC");
+        assert_eq!(product_sort_clause(None, true), "p.created_at DESC");
+        assert!(product_sort_clause(Some("price_asc"), false).starts_with("p.price ASC"));
+        assert_eq!(product_sort_clause(Some("price; DROP TABLE products; --"), false), "p.created_at DESC");
+        assert!(order_sort_clause(Some("total_desc")).starts_with("total DESC"));
+        assert_eq!(order_sort_clause(Some("created_at DESC, (SELECT 1)")), "created_at DESC");
+    }
+    fn order_with_totals(subtotal: i64, tax: i64, shipping: i64, total: i64) -> Order {
+        Order { id: Uuid::nil(), order_number: "ORD-1".into(), customer_id: None, customer_email: "a@example.com".into(), status: "pending".into(), subtotal, tax, shipping, total, currency: "NGN".into(), shipping_address: serde_json::json!({}), billing_address: serde_json::json!({}), payment_status: "pending".into(), fulfillment_status: "unfulfilled".into(), created_at: Utc::now(), updated_at: Utc::now() }
+    }
+    fn order_item(quantity: i32, unit_price: i64, total: i64) -> OrderItem {
+        OrderItem { id: Uuid::new_v4(), order_id: Uuid::nil(), product_id: Uuid::new_v4(), sku: "SKU".into(), name: "Item".into(), quantity, unit_price, total }
+    }
+    #[test]
+    fn test_category_change_event() {
+        let (product, shoes, bags) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        let event = serde_json::to_value(category_change_event(product, Some(shoes), Some(bags)).unwrap()).unwrap();
+        assert_eq!(event, serde_json::json!({"aggregate": "Product", "type": "CategoryChanged", "product_id": product, "from": shoes, "to": bags}));
+        assert!(category_change_event(product, Some(shoes), Some(shoes)).is_none());
+    }
+    #[test]
+    fn test_search_term() {
+        assert_eq!(search_term("  mug "), Some("mug".to_string()));
+        assert_eq!(search_term("50%_off"), Some("50\\%\\_off".to_string()));
+        assert_eq!(search_term("   "), None);
+    }
+    #[test]
+    fn test_attribute_filters() {
+        let query: Vec<(String, String)> = [("attr", "material:cotton"), ("page", "2"), ("attr", "size:XL")].iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        assert_eq!(attribute_filters(&query).unwrap(), (vec!["material".to_string(), "size".to_string()], vec!["cotton".to_string(), "XL".to_string()]));
+        assert_eq!(attribute_filters(&[("attr".to_string(), "cotton".to_string())]).unwrap_err().0, StatusCode::BAD_REQUEST);
+    }
+    #[test]
+    fn test_cdn_image_url() {
+        assert_eq!(cdn_image_url("https://storage.example.com/products/shoe.jpg", "https://cdn.example.com/", None), "https://cdn.example.com/products/shoe.jpg");
+        assert_eq!(cdn_image_url("products/shoe.jpg", "https://cdn.example.com", Some("w=800")), "https://cdn.example.com/products/shoe.jpg?w=800");
+        assert_eq!(cdn_image_url("http://origin/a.jpg?v=2", "https://cdn.example.com", Some("w=800")), "https://cdn.example.com/a.jpg?v=2&w=800");
+    }
+    fn shoe() -> Product {
+        Product { id: Uuid::nil(), sku: "SKU-1".into(), name: "Shoe".into(), description: None, price: 1000, compare_at_price: None, cost: None, currency: "NGN".into(), category_id: None, inventory_quantity: 1, max_per_order: None, status: "active".into(), inventory_policy: "deny".into(), preorder_until: None, images: vec!["https://storage.example.com/shoe.jpg".into()], tags: vec![], metadata: serde_json::json!({}), created_at: Utc::now(), updated_at: Utc::now(), stock_status: None }
+    }
+    #[test]
+    fn test_product_stock_status() {
+        let now = Utc::now();
+        assert_eq!(product_stock_status(&shoe(), now), StockStatus::InStock);
+        assert_eq!(product_stock_status(&Product { inventory_quantity: 0, ..shoe() }, now), StockStatus::OutOfStock);
+        assert_eq!(product_stock_status(&Product { inventory_quantity: 0, inventory_policy: "continue".into(), ..shoe() }, now), StockStatus::Backorder);
+        assert_eq!(product_stock_status(&Product { inventory_quantity: 0, preorder_until: Some(now + chrono::Duration::days(7)), ..shoe() }, now), StockStatus::Preorder);
+        assert_eq!(serde_json::to_value(StockStatus::OutOfStock).unwrap(), "out_of_stock");
+    }
+    #[test]
+    fn test_product_images_use_cdn() {
+        let product = shoe();
+        assert_eq!(with_cdn_images(product.clone(), Some("https://cdn.example.com"), None).images, vec!["https://cdn.example.com/shoe.jpg"]);
+        assert_eq!(with_cdn_images(product, None, None).images, vec!["https://storage.example.com/shoe.jpg"]);
+    }
+    #[test]
+    fn test_formatted_order_totals() {
+        let response = OrderResponse::from(order_with_totals(120000, 12000, 0, 132000));
+        assert_eq!(response.formatted.subtotal, "₦1,200.00");
+        assert_eq!(response.formatted.total, "₦1,320.00");
+        assert_eq!(serde_json::to_value(&response).unwrap()["total"], 132000);
+    }
+    #[test]
+    fn test_verify_totals() {
+        let items = vec![order_item(2, 500, 1000), order_item(1, 300, 300)];
+        assert_eq!(verify_totals(&order_with_totals(1300, 100, 200, 1600), &items), Ok(()));
+        let discrepancies = verify_totals(&order_with_totals(1000, 100, 200, 1600), &items).unwrap_err();
+        assert_eq!(discrepancies, vec![TotalDiscrepancy { field: "subtotal".into(), stored: 1000, computed: 1300 }]);
+    }
+    #[test]
+    fn test_fulfillment_transitions() {
+        assert!(fulfillment_transition_allowed("unfulfilled", "fulfilled"));
+        assert!(fulfillment_transition_allowed("partial", "partial"));
+        assert!(!fulfillment_transition_allowed("fulfilled", "unfulfilled"));
+        assert!(!fulfillment_transition_allowed("unfulfilled", "lost"));
+    }
+    #[test]
+    fn test_order_status_for_fulfillment() {
+        assert_eq!(order_status_for_fulfillment("confirmed", "pending", "fulfilled"), Ok(Some("shipped")));
+        assert_eq!(order_status_for_fulfillment("pending", "paid", "fulfilled"), Ok(Some("shipped")));
+        assert_eq!(order_status_for_fulfillment("shipped", "paid", "delivered"), Ok(Some("delivered")));
+        assert_eq!(order_status_for_fulfillment("delivered", "paid", "fulfilled"), Ok(None));
+        assert_eq!(order_status_for_fulfillment("processing", "paid", "partial"), Ok(None));
+        assert_eq!(order_status_for_fulfillment("cancelled", "pending", "fulfilled"), Err("order is cancelled".to_string()));
+        assert!(order_status_for_fulfillment("refunded", "refunded", "delivered").is_err());
+        assert_eq!(order_status_for_fulfillment("pending", "pending", "fulfilled"), Err("order is pending and payment is pending".to_string()));
+        assert!(order_status_for_fulfillment("pending", "pending", "partial").is_err());
+        assert!(order_status_for_fulfillment("pending", "pending", "delivered").is_err());
+    }
+    #[test]
+    fn test_validate_address() {
+        assert!(validate_address(&serde_json::json!({"name": "Ada", "street1": "1 Marina", "city": "Lagos", "zip": "101001", "country": "NG"})).is_ok());
+        assert_eq!(validate_address(&serde_json::json!({"name": "Ada", "street1": " ", "city": "Lagos", "country": "NG"})), Err("Missing address fields: street1, zip".to_string()));
+    }
+    #[test]
+    fn test_validate_session_id() {
+        assert!(validate_session_id("sess-0f3a9c").is_ok());
+        assert_eq!(validate_session_id(&"a".repeat(MAX_SESSION_ID_LEN + 1)).unwrap_err().0, StatusCode::BAD_REQUEST);
+        assert!(validate_session_id("a/b").is_err());
+        assert!(validate_session_id("").is_err());
+    }
+    #[test]
+    fn test_order_rate_limit_per_email() {
+        let limiter = OrderRateLimiter::new(2, chrono::Duration::hours(1));
+        let now = Utc::now();
+        let check = |email: &str, at| { let allowed = limiter.allows(email, at); if allowed { limiter.record(email, at); } allowed };
+        assert!(check("a@example.com", now));
+        assert!(check("A@example.com", now));
+        assert!(!check("a@example.com", now));
+        assert!(check("b@example.com", now));
+        assert!(check("a@example.com", now + chrono::Duration::hours(2)));
+    }
+    #[test]
+    fn test_order_rate_limit_records_and_prunes() {
+        let limiter = OrderRateLimiter::new(1, chrono::Duration::hours(1));
+        let now = Utc::now();
+        assert!(limiter.allows("a@example.com", now));
+        assert!(limiter.allows("a@example.com", now), "allows must not use up the attempt");
+        limiter.record("a@example.com", now);
+        limiter.record("b@example.com", now + chrono::Duration::minutes(30));
+        assert!(!limiter.allows("A@example.com", now));
+        limiter.prune(now + chrono::Duration::minutes(61));
+        assert_eq!(limiter.hits.len(), 1);
+        assert!(limiter.allows("a@example.com", now + chrono::Duration::minutes(61)));
+        limiter.prune(now + chrono::Duration::hours(2));
+        assert_eq!(limiter.hits.len(), 0);
+    }
+    fn checkout_line(sku: &str, price: i64, inventory_quantity: i32, quantity: i32) -> CheckoutLine {
+        CheckoutLine { product_id: Uuid::new_v4(), sku: sku.into(), name: sku.into(), price, currency: "NGN".into(), inventory_quantity, quantity }
+    }
+    #[test]
+    fn test_checkout_lines() {
+        let lines = vec![checkout_line("A", 1000, 5, 2), checkout_line("B", 250, 1, 3)];
+        assert_eq!(checkout_subtotal(&lines), 2750);
+        assert_eq!(find_shortage(&lines).map(|l| l.sku.as_str()), Some("B"));
+        assert!(find_shortage(&lines[..1]).is_none());
+    }
+    #[test]
+    fn test_order_currency() {
+        let lines = vec![checkout_line("A", 1000, 5, 2), checkout_line("B", 250, 1, 3)];
+        assert_eq!(order_currency(&lines), Ok("NGN"));
+        let mixed = vec![checkout_line("A", 1000, 5, 2), CheckoutLine { currency: "USD".into(), ..checkout_line("B", 250, 1, 3) }];
+        assert_eq!(order_currency(&mixed), Err((StatusCode::UNPROCESSABLE_ENTITY, "Order mixes currencies: NGN, USD".to_string())));
+        assert_eq!(order_currency(&[]).unwrap_err().0, StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    #[test]
+    fn test_next_default_address() {
+        let address = |created_at| CustomerAddress { id: Uuid::new_v4(), customer_id: Uuid::nil(), address: serde_json::json!({}), is_default: false, created_at };
+        let (older, newer) = (address(Utc::now() - chrono::Duration::days(2)), address(Utc::now()));
+        assert_eq!(next_default(&[newer.clone(), older.clone()]), Some(older.id));
+        assert_eq!(next_default(&[]), None);
+    }
+    #[test]
+    fn test_stock_variance() {
+        assert_eq!(stock_variance(10, 8), -2);
+        assert_eq!(stock_variance(10, 12), 2);
+    }
 }